@@ -1,6 +1,70 @@
+use std::path::Path;
+
+/// Language of a source file, deciding which compiler and flags are used to build it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Language {
+    C,
+    Cpp,
+    Asm
+}
+
+impl Language {
+    fn from_ext(ext: &str) -> Option<Language> {
+        match ext {
+            "c" => Some(Language::C),
+            "cpp" | "cc" | "cxx" | "c++" | "cp" | "CPP" | "C" => Some(Language::Cpp),
+            "s" | "S" | "sx" | "asm" => Some(Language::Asm),
+            _ => None
+        }
+    }
+
+    fn compiler_var(self) -> &'static str {
+        match self {
+            Language::C => "CC",
+            Language::Cpp => "CXX",
+            Language::Asm => "AS"
+        }
+    }
+
+    fn flags_var(self) -> &'static str {
+        match self {
+            Language::C => "CFLAGS",
+            Language::Cpp => "CXXFLAGS",
+            Language::Asm => "ASFLAGS"
+        }
+    }
+}
+
+/// What the link step produces
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum OutputType {
+    Executable,
+    StaticLib,
+    SharedLib,
+    Both //static and shared library
+}
+
+impl OutputType {
+    fn static_lib(self) -> bool {
+        matches!(self, OutputType::StaticLib | OutputType::Both)
+    }
+
+    fn shared_lib(self) -> bool {
+        matches!(self, OutputType::SharedLib | OutputType::Both)
+    }
+}
+
+/// What to do when two sources would be compiled to the same object
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum CollisionPolicy {
+    Error,
+    Warn,
+    Disambiguate //objects are put in subfolders named after their source directory
+}
+
 struct SourceDir<'a> {
     dir: &'a str,
-    ext: Option<&'a str>,
+    ext: Option<Vec<&'a str>>,
     depth: Option<i64>,
     included: bool,
     exclude: Vec<&'a str>
@@ -21,16 +85,33 @@ impl <'a> SourceDir<'a> {
 pub struct Config <'a> {
     keep_source_dir_names: bool,
     source: Vec<SourceDir<'a>>,
-    default_ext: &'a str,
+    kind: Language,
+    default_ext: Vec<&'a str>,
     exclude_dir: Vec<&'a str>,
     include_dir: Vec<&'a str>,
     obj_dir: &'a str,
     bin_dir: &'a str,
+    depfiles: bool,
+    object_collisions: CollisionPolicy,
+    regenerate: bool,
+    static_listing: bool, //sources listed in the Makefile at generation time instead of found by make
+
+    output_type: OutputType,
+    lib_name: Option<&'a str>,
+    version: Option<String>,
+
+    executables: Vec<Executable<'a>>,
+    main: Option<Vec<&'a str>>,
+    tests: Option<TestConfig<'a>>,
 
     default_config: BuildConfig<'a>,
     alt_targets: Vec<Target<'a>>,
+    profiles: Vec<Profile<'a>>,
+    default_profile: Option<&'a str>,
 
-    pub output_file: Option<&'a str>
+    pub name: Option<&'a str>, //name of the document, selected with --document
+    pub output_file: Option<&'a str>,
+    pub env_defaults: Vec<(String, String)> //defaults of the environment variables left for make to expand
 }
      
 #[derive(Debug)]
@@ -48,23 +129,121 @@ impl <'a> Target<'a> {
     }
 }
 
+/// Executable built from the shared objects plus its own entry point sources
+#[derive(Debug)]
+pub struct Executable<'a> {
+    name: &'a str,
+    main: Vec<&'a str>,
+    ldflags: Option<&'a str>,
+    libs: Option<Vec<&'a str>>
+}
+
+/// Test framework whose libraries and flags are added to the test executables
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum TestFramework {
+    Criterion,
+    GoogleTest,
+    Catch2
+}
+
+impl TestFramework {
+    fn libs(self) -> &'static [&'static str] {
+        match self {
+            TestFramework::Criterion => &["criterion"],
+            TestFramework::GoogleTest => &["gtest_main", "gtest"],
+            TestFramework::Catch2 => &["Catch2Main", "Catch2"]
+        }
+    }
+
+    fn ldflags(self) -> &'static str {
+        match self {
+            TestFramework::GoogleTest => "-pthread",
+            _ => ""
+        }
+    }
+
+    /// Whether the tests are written in C++, and linked with the C++ compiler
+    fn is_cpp(self) -> bool {
+        matches!(self, TestFramework::GoogleTest | TestFramework::Catch2)
+    }
+}
+
+/// Test executables, linked against the project objects minus the entry points
+#[derive(Debug)]
+pub struct TestConfig<'a> {
+    dir: &'a str,
+    ext: Option<Vec<&'a str>>,
+    runner: Option<&'a str>, //name of the single executable running all tests, one per file if None
+    framework: Option<TestFramework>,
+    ldflags: Option<&'a str>,
+    libs: Option<Vec<&'a str>>
+}
+
+impl <'a> TestConfig<'a> {
+    pub fn new(dir: &'a str) -> Self {
+        Self {
+            dir,
+            ext: None,
+            runner: None,
+            framework: None,
+            ldflags: None,
+            libs: None
+        }
+    }
+}
+
+/// Build variant with its own object and binary directories, selected at make time
+#[derive(Debug)]
+pub struct Profile<'a> {
+    name: &'a str,
+    obj_dir: Option<&'a str>,
+    bin_dir: Option<&'a str>,
+    config: BuildConfig<'a>
+}
+
+impl <'a> Profile<'a> {
+    pub fn new(name: &'a str) -> Self {
+        Self {
+            name,
+            obj_dir: None,
+            bin_dir: None,
+            config: BuildConfig::default()
+        }
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct BuildConfig<'a> {
     exec_name: Option<&'a str>,
-    compiler: Option<&'a str>,
-    cflags: Option<&'a str>,
+    compiler: Option<&'a str>, //compiler for the language of the project kind
+    c_compiler: Option<&'a str>,
+    cxx_compiler: Option<&'a str>,
+    assembler: Option<&'a str>,
+    archiver: Option<&'a str>,
     ldflags: Option<&'a str>,
-    libs: Option<Vec<&'a str>>
+    asflags: Option<&'a str>,
+    libs: Option<Vec<&'a str>>,
+
+    std: Option<Vec<&'a str>>,
+    optimize: Option<String>,
+    debug_info: Option<bool>,
+    warnings: Option<Vec<&'a str>>,
+    defines: Option<Vec<&'a str>>,
+    extra: Option<&'a str>
 }
 
 pub struct DefaultConfig {
     exec_name: &'static str, 
-    compiler: &'static str,
-    cflags: &'static str,
+    c_compiler: &'static str,
+    cxx_compiler: &'static str,
+    assembler: &'static str,
+    archiver: &'static str,
     ldflags: &'static str,
+    asflags: &'static str,
     libs: Vec<&'static str>
 }
 
+#[allow(dead_code)]
 enum CompileMsgType <'a> {
     Command,
     None,
@@ -72,6 +251,7 @@ enum CompileMsgType <'a> {
     Custom(&'a str) //string should contain "{file}"
 }
 
+#[allow(dead_code)]
 pub struct DisplayConfig<'a> {
     compile_msg: CompileMsgType<'a>,
     start_msg: bool,
@@ -84,29 +264,113 @@ impl<'a> Config <'a>{
         Config { 
             source: Vec::from([SourceDir::new()]),
             keep_source_dir_names: false,
-            default_ext: "cpp",
+            kind: Language::Cpp,
+            default_ext: Vec::from(["cpp"]),
             exclude_dir: Vec::new(),
             include_dir: Vec::new(),
             obj_dir: "obj",
             bin_dir: ".",
+            depfiles: true,
+            object_collisions: CollisionPolicy::Error,
+            regenerate: true,
+            static_listing: false,
+
+            output_type: OutputType::Executable,
+            lib_name: None,
+            version: None,
+
+            executables: Vec::new(),
+            main: None,
+            tests: None,
 
             default_config: BuildConfig::default(),
             alt_targets: Vec::new(),
+            profiles: Vec::new(),
+            default_profile: None,
 
-            output_file: None
+            name: None,
+            output_file: None,
+            env_defaults: Vec::new()
         }    
     }
 }
 
+impl Config<'_> {
+    fn source_exts<'s>(&'s self, source: &'s SourceDir) -> &'s [&'s str] {
+        match &source.ext {
+            Some(exts) => exts,
+            None => &self.default_ext
+        }
+    }
+
+    /// Extensions of the test sources, cpp for the C++ frameworks unless the project already is C++
+    fn test_exts<'s>(&'s self, tests: &'s TestConfig) -> &'s [&'s str] {
+        match &tests.ext {
+            Some(exts) => exts,
+            None if tests.framework.is_some_and(TestFramework::is_cpp) && !self.default_ext.iter().any(|ext| self.language_of(ext) == Language::Cpp) => &["cpp"],
+            None => &self.default_ext
+        }
+    }
+
+    fn language_of(&self, ext: &str) -> Language {
+        Language::from_ext(ext).unwrap_or(self.kind)
+    }
+
+    fn uses_language(&self, lang: Language) -> bool {
+        self.source.iter().any(|source| self.source_exts(source).iter().any(|ext| self.language_of(ext) == lang))
+    }
+
+    /// Path of the object built from a source file, as it appears in the Makefile's OBJS
+    fn object_path(&self, src: &str) -> Option<String> {
+        let src = src.strip_prefix("./").unwrap_or(src);
+        for source in &self.source {
+            let dir = source.dir.trim_end_matches('/');
+            let rest = if dir == "." {
+                Some(src)
+            } else {
+                src.strip_prefix(dir).and_then(|rest| rest.strip_prefix('/'))
+            };
+            if let Some(rest) = rest {
+                return Some(format!("$(OBJ_DIR)/{}", self.object_name(dir, rest)));
+            }
+        }
+        None
+    }
+
+    /// Path of the object built from a source, relative to OBJ_DIR, given the path of the source in its source directory
+    fn object_name(&self, dir: &str, rest: &str) -> String {
+        let prefix = if self.keep_source_dir_names {format!("{dir}/")} else {String::new()};
+        format!("{prefix}{}", Path::new(rest).with_extension("o").display())
+    }
+}
+
+/// Path standing for stdin as input file, or stdout as output file
+pub const STDIO: &str = "-";
+
+/// How goombuild was run, so that the generated Makefile can run it again when the config changes
+pub struct Invocation<'a> {
+    pub input_file: &'a str,
+    pub base_files: Vec<&'a str>, //files the config extends, that the Makefile depends on too
+    pub args: Vec<&'a str>
+}
+
 fn init_default() -> DefaultConfig {
     DefaultConfig {
         exec_name: "main",
-        compiler: "gcc",
-        cflags: "",
+        c_compiler: "gcc",
+        cxx_compiler: "g++",
+        assembler: "$(CC)", //assembly goes through the C compiler driver unless specified
+        archiver: "ar",
         ldflags: "",
+        asflags: "",
         libs: Vec::new()
     }
 }
 
 mod write;
-mod read;
\ No newline at end of file
+mod read;
+mod sources;
+mod dump;
+
+pub use dump::{dump_all, DumpFormat};
+pub use sources::defines_main;
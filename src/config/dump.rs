@@ -0,0 +1,253 @@
+use std::str::FromStr;
+
+use yaml_rust2::{yaml::Hash, Yaml, YamlEmitter};
+
+use crate::json::Json;
+
+use super::{init_default, write::profile_dir, BuildConfig, CollisionPolicy, Config, Language, OutputType, TestFramework};
+
+/// Format of the effective config printed by --dump-config
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DumpFormat {
+    Yaml,
+    Json
+}
+
+impl FromStr for DumpFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "yaml" => Ok(DumpFormat::Yaml),
+            "json" => Ok(DumpFormat::Json),
+            _ => Err("dump format should be either yaml or json")
+        }
+    }
+}
+
+fn language_name(lang: Language) -> &'static str {
+    match lang {
+        Language::C => "c",
+        Language::Cpp => "cpp",
+        Language::Asm => "asm"
+    }
+}
+
+fn str_list(values: &[&str]) -> Json {
+    Json::Array(values.iter().map(|value| Json::from(*value)).collect())
+}
+
+fn to_yaml(json: Json) -> Yaml {
+    match json {
+        Json::Null => Yaml::Null,
+        Json::Bool(b) => Yaml::Boolean(b),
+        Json::Number(n) => Yaml::Integer(n),
+        Json::String(str) => Yaml::String(str),
+        Json::Array(values) => Yaml::Array(values.into_iter().map(to_yaml).collect()),
+        Json::Object(fields) => {
+            let mut hash = Hash::new();
+            for (key, value) in fields {
+                hash.insert(Yaml::from_str(key), to_yaml(value));
+            }
+            Yaml::Hash(hash)
+        }
+    }
+}
+
+impl BuildConfig<'_> {
+    /// The values set in this config, under the keys they are read from
+    fn dump(&self, kind: Language) -> Vec<(&'static str, Json)> {
+        let mut fields = Vec::new();
+        let mut push = |key, value: Option<Json>| if let Some(value) = value {fields.push((key, value))};
+        push("exec", self.exec_name.map(Json::from));
+        push("c_compiler", self.compiler_for(Language::C, kind).map(Json::from));
+        push("cxx_compiler", self.compiler_for(Language::Cpp, kind).map(Json::from));
+        push("assembler", self.compiler_for(Language::Asm, kind).map(Json::from));
+        push("archiver", self.archiver.map(Json::from));
+        push("link_flags", self.ldflags.map(Json::from));
+        push("asm_flags", self.asflags.map(Json::from));
+        push("libs", self.libs.as_deref().map(str_list));
+        push("std", self.std.as_deref().map(str_list));
+        push("optimize", self.optimize.clone().map(Json::from));
+        push("debug_info", self.debug_info.map(Json::from));
+        push("warnings", self.warnings.as_deref().map(str_list));
+        push("defines", self.defines.as_deref().map(str_list));
+        push("extra", self.extra.map(Json::from));
+        fields
+    }
+}
+
+impl Config<'_> {
+    /// The default build config, with every value goombuild would use when it isn't set
+    fn dump_default_config(&self) -> Json {
+        let defaults = init_default();
+        let config = &self.default_config;
+        let mut fields = vec![
+            ("exec", config.exec_name.unwrap_or(defaults.exec_name).into()),
+            ("c_compiler", config.compiler_for(Language::C, self.kind).unwrap_or(defaults.c_compiler).into()),
+            ("cxx_compiler", config.compiler_for(Language::Cpp, self.kind).unwrap_or(defaults.cxx_compiler).into()),
+            ("assembler", config.compiler_for(Language::Asm, self.kind).unwrap_or(defaults.assembler).into()),
+            ("archiver", config.archiver.unwrap_or(defaults.archiver).into()),
+            ("link_flags", config.ldflags.unwrap_or(defaults.ldflags).into()),
+            ("asm_flags", config.asflags.unwrap_or(defaults.asflags).into()),
+            ("libs", str_list(config.libs.as_deref().unwrap_or(&defaults.libs))),
+            ("std", str_list(config.std.as_deref().unwrap_or_default())),
+            //the compiler's default level
+            ("optimize", config.optimize.clone().into()),
+            ("debug_info", config.debug_info.unwrap_or(false).into()),
+            ("warnings", str_list(config.warnings.as_deref().unwrap_or_default())),
+            ("defines", str_list(config.defines.as_deref().unwrap_or_default())),
+            ("extra", config.extra.unwrap_or("").into())
+        ];
+        fields.retain(|(key, _)| *key != "archiver" || self.output_type.static_lib());
+        Json::Object(fields)
+    }
+
+    /// The effective config, once every default is applied. Targets and profiles only show what they override
+    fn dump(&self, out_file: &str) -> Json {
+        let mut fields: Vec<(&'static str, Json)> = vec![
+            ("kind", language_name(self.kind).into()),
+            ("type", match self.output_type {
+                OutputType::Executable => "executable",
+                OutputType::StaticLib => "static_lib",
+                OutputType::SharedLib => "shared_lib",
+                OutputType::Both => "both"
+            }.into())
+        ];
+        if self.output_type != OutputType::Executable {
+            fields.push(("lib_name", self.lib_name.or(self.default_config.exec_name).unwrap_or(init_default().exec_name).into()));
+            fields.push(("version", self.version.clone().into()));
+        }
+        if let Some(name) = self.name {
+            fields.push(("name", name.into()));
+        }
+        fields.push(("output_file", out_file.into()));
+
+        let sources = self.source.iter().map(|source| {
+            let mut exclude = source.exclude.clone();
+            exclude.extend(&self.exclude_dir);
+            Json::Object(vec![
+                ("dir", source.dir.into()),
+                ("ext", str_list(self.source_exts(source))),
+                ("depth", source.depth.into()),
+                ("exclude", str_list(&exclude)),
+                ("included", source.included.into())
+            ])
+        }).collect();
+        fields.push(("sources", Json::Array(sources)));
+        fields.push(("include", str_list(&self.include_dir)));
+        fields.push(("obj_dir", self.obj_dir.into()));
+        fields.push(("bin_dir", self.bin_dir.into()));
+        fields.push(("keep_source_dir_names", self.keep_source_dir_names.into()));
+        fields.push(("depfiles", self.depfiles.into()));
+        fields.push(("regenerate", self.regenerate.into()));
+        fields.push(("object_collisions", match self.object_collisions {
+            CollisionPolicy::Error => "error",
+            CollisionPolicy::Warn => "warn",
+            CollisionPolicy::Disambiguate => "disambiguate"
+        }.into()));
+        fields.push(("source_listing", if self.static_listing {"static"} else {"shell"}.into()));
+        fields.push(("default_config", self.dump_default_config()));
+
+        let targets = self.alt_targets.iter().map(|target| {
+            let mut target_fields = vec![("name", target.name.into())];
+            target_fields.extend(target.config.dump(self.kind));
+            Json::Object(target_fields)
+        }).collect();
+        fields.push(("targets", Json::Array(targets)));
+
+        let executables = self.executables.iter().map(|exec| {
+            let mut exec_fields = vec![("name", exec.name.into()), ("main", str_list(&exec.main))];
+            if let Some(ldflags) = exec.ldflags {exec_fields.push(("link_flags", ldflags.into()))}
+            if let Some(libs) = &exec.libs {exec_fields.push(("libs", str_list(libs)))}
+            Json::Object(exec_fields)
+        }).collect();
+        fields.push(("executables", Json::Array(executables)));
+        if let Some(main) = &self.main {
+            fields.push(("main", str_list(main)));
+        }
+
+        if let Some(tests) = &self.tests {
+            let mut test_fields = vec![
+                ("dir", tests.dir.into()),
+                ("ext", str_list(self.test_exts(tests))),
+                ("mode", if tests.runner.is_some() {"runner"} else {"per_file"}.into())
+            ];
+            if let Some(runner) = tests.runner {test_fields.push(("name", runner.into()))}
+            if let Some(framework) = tests.framework {
+                test_fields.push(("framework", match framework {
+                    TestFramework::Criterion => "criterion",
+                    TestFramework::GoogleTest => "gtest",
+                    TestFramework::Catch2 => "catch2"
+                }.into()));
+            }
+            if let Some(ldflags) = tests.ldflags {test_fields.push(("link_flags", ldflags.into()))}
+            if let Some(libs) = &tests.libs {test_fields.push(("libs", str_list(libs)))}
+            fields.push(("tests", Json::Object(test_fields)));
+        }
+
+        let profiles = self.profiles.iter().map(|profile| {
+            let mut profile_fields = vec![
+                ("name", profile.name.into()),
+                ("obj_dir", profile.obj_dir.map_or_else(|| profile_dir(self.obj_dir, profile.name), str::to_owned).into()),
+                ("bin_dir", profile.bin_dir.map_or_else(|| profile_dir(self.bin_dir, profile.name), str::to_owned).into())
+            ];
+            profile_fields.extend(profile.config.dump(self.kind));
+            Json::Object(profile_fields)
+        }).collect();
+        fields.push(("profiles", Json::Array(profiles)));
+        if let Some(profile) = self.profiles.first() {
+            fields.push(("default_profile", self.default_profile.unwrap_or(profile.name).into()));
+        }
+
+        Json::Object(fields)
+    }
+}
+
+/// The effective configs of several documents, as a YAML stream or a JSON array (a single one is dumped alone)
+pub fn dump_all(configs: &[(&Config, &str)], format: DumpFormat) -> String {
+    let mut dumps: Vec<Json> = configs.iter().map(|(config, out_file)| config.dump(out_file)).collect();
+    match format {
+        DumpFormat::Json if dumps.len() == 1 => format!("{:#}", dumps.remove(0)),
+        DumpFormat::Json => format!("{:#}", Json::Array(dumps)),
+        DumpFormat::Yaml => {
+            let mut out = String::new();
+            for dump in dumps {
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                //emitting into a String can't fail
+                let _ = YamlEmitter::new(&mut out).dump(&to_yaml(dump));
+            }
+            out
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust2::YamlLoader;
+
+    use crate::config::Config;
+    use crate::diagnostics::Diagnostics;
+    use super::{dump_all, DumpFormat};
+
+    #[test]
+    fn several_documents() {
+        let docs = YamlLoader::load_from_str("name: linux\nkind: c\n---\nname: win\nkind: cpp\n").unwrap();
+        let mut diagnostics = Diagnostics::new(true);
+        let configs: Vec<Config> = docs.iter().map(|doc| Config::read(doc, &mut diagnostics)).collect();
+        assert!(!diagnostics.has_errors());
+        let dumps = [(&configs[0], "Makefile.linux"), (&configs[1], "Makefile.win")];
+
+        let yaml = dump_all(&dumps, DumpFormat::Yaml);
+        let docs = YamlLoader::load_from_str(&yaml).unwrap();
+        assert_eq!(docs.len(), 2, "{yaml}");
+        assert_eq!(docs[1]["name"].as_str(), Some("win"));
+        assert_eq!(docs[1]["output_file"].as_str(), Some("Makefile.win"));
+
+        let json = dump_all(&dumps, DumpFormat::Json);
+        assert!(json.starts_with('['), "{json}");
+        assert!(dump_all(&dumps[..1], DumpFormat::Json).starts_with('{'));
+    }
+}
@@ -1,8 +1,27 @@
-use crate::read_yaml::{array_or_string_into_vec, get_array, get_bool, get_data, get_int, get_str, handle_wrong_type, try_map_option, ContentError, ContextfulError, ContextfulMaybe, YamlResult};
+use crate::diagnostics::Diagnostics;
+use crate::read_yaml::{check_keys, get_array, get_bool, get_data, get_int, get_str, get_str_list, get_str_or_int, handle_wrong_type, ContextfulError, YamlResult};
 
-use super::{BuildConfig, Config, SourceDir, Target};
+use super::{BuildConfig, CollisionPolicy, Config, Executable, Language, OutputType, Profile, SourceDir, Target, TestConfig, TestFramework};
 use yaml_rust2::{yaml::Hash, Yaml};
 
+const SOURCE_DIR_KEYS: [&str; 5] = ["dir", "ext", "depth", "exclude", "included"];
+const BUILD_CONFIG_KEYS: [&str; 23] = [
+    "exec", "libs", "link_flags", "ldflags", "compiler", "c_compiler", "cc", "cxx_compiler", "cxx",
+    "assembler", "as", "archiver", "ar", "asm_flags", "asflags", "std", "optimize", "debug_info",
+    "warnings", "defines", "extra", "compile_flags", "cflags"
+];
+const TARGET_KEYS: [&str; 1] = ["name"];
+const EXECUTABLE_KEYS: [&str; 5] = ["name", "main", "link_flags", "ldflags", "libs"];
+const TEST_KEYS: [&str; 8] = ["mode", "name", "framework", "dir", "ext", "link_flags", "ldflags", "libs"];
+const PROFILE_KEYS: [&str; 3] = ["name", "obj_dir", "bin_dir"];
+/// Targets of the generated Makefile, that profiles can't be named after as each profile gets a target too
+const GENERATED_TARGETS: [&str; 7] = ["all", "start", "clean", "clean-profile", "clear", "distclean", "test"];
+const CONFIG_KEYS: [&str; 27] = [
+    "kind", "sources", "src_dir", "src_exclude", "src_depth", "src_ext", "include", "keep_source_dir_names",
+    "obj_dir", "bin_dir", "depfiles", "regenerate", "object_collisions", "source_listing", "type", "lib_name",
+    "version", "targets", "executables", "main", "tests", "profiles", "default_profile", "output-file", "output_file",
+    "vars", "name"
+];
 
 
 fn get_dir_name<'a>(data: &'a Hash, key: &'static str) -> YamlResult<&'a str> {
@@ -12,81 +31,208 @@ fn get_dir_name<'a>(data: &'a Hash, key: &'static str) -> YamlResult<&'a str> {
     }
 }
 
+/// Reads a value that can't be left out, recording `missing` if it is
+fn require<T>(value: YamlResult<T>, missing: impl FnOnce() -> ContextfulError, diagnostics: &mut Diagnostics) -> Option<T> {
+    match value {
+        Ok(None) => {
+            diagnostics.error(missing().with_code("missing-value"));
+            None
+        },
+        value => diagnostics.value(value)
+    }
+}
+
 impl <'a> SourceDir <'a> {
     fn read_from_str(&mut self, str: &'a str){
         self.dir = str;
     }
 
-    fn read_from_hash(&mut self, hash: &'a Hash) -> Result<(), ContextfulError>{
-        if let Some(str) = get_str(&hash, "dir")?{
-            self.dir = str;
-        } else {
-            return Err(ContextfulError::from("Source directory is missing").add_context("In source directory"))
-        }
-        self.ext = get_str(&hash, "ext")?;
-        self.depth = get_int(&hash, "depth")?;
-
-        if let Some(yaml) = get_data(hash, "exclude") {self.exclude = array_or_string_into_vec(yaml)?}        
-        if let Some(b) = get_bool(&hash, "included")? {self.included = b};
-        Ok(())
+    fn read_from_hash(&mut self, hash: &'a Hash, diagnostics: &mut Diagnostics) {
+        let missing = || ContextfulError::from("Source directories need a dir").at(hash);
+        let Some(dir) = require(get_str(hash, "dir"), missing, diagnostics) else {return};
+        self.dir = dir;
+        diagnostics.in_context(format!("In source directory {dir}"), |diagnostics| {
+            check_keys(hash, &[&SOURCE_DIR_KEYS], diagnostics);
+            self.ext = diagnostics.value(get_str_list(hash, "ext"));
+            self.depth = diagnostics.value(get_int(hash, "depth"));
+            if let Some(exclude) = diagnostics.value(get_str_list(hash, "exclude")) {self.exclude = exclude}
+            if let Some(b) = diagnostics.value(get_bool(hash, "included")) {self.included = b};
+        });
     }
 
-    fn read_from_hash_or_string(yaml: &'a Yaml) -> Result<SourceDir<'a>, ContextfulError> {
+    fn read_from_hash_or_string(yaml: &'a Yaml, diagnostics: &mut Diagnostics) -> Option<SourceDir<'a>> {
         let mut source = Self::new();
         match yaml {
-            Yaml::String(str) => Ok(source.read_from_str(str)),
-            Yaml::Hash(hash) => source.read_from_hash(hash),
-            val => Err(handle_wrong_type(val, "string or object")).into()
-        }?;
-        Ok(source)
+            Yaml::String(str) => source.read_from_str(str),
+            Yaml::Hash(hash) => source.read_from_hash(hash, diagnostics),
+            val => {
+                diagnostics.error(handle_wrong_type(val, "string or object").add_context("sources property"));
+                return None
+            }
+        };
+        Some(source)
     }
 }
 
+const OPTIMIZE_LEVELS: [&str; 8] = ["0", "1", "2", "3", "s", "z", "g", "fast"];
+
 impl <'a> BuildConfig<'a> {
-    fn read(&mut self, data: &'a Hash) -> Result<(), ContextfulError>{
-        self.exec_name = get_str(data, "exec")?;
-        self.libs = try_map_option(get_data(data, "libs"), array_or_string_into_vec)?;
-        self.ldflags = get_str(data, "link_flags")?.or(get_str(data, "ldflags")?);
-        self.cflags = get_str(data, "compile_flags")?.or(get_str(data, "cflags")?);
-        self.compiler = get_str(data, "compiler")?;
-
-        Ok(())
+    fn read(&mut self, data: &'a Hash, diagnostics: &mut Diagnostics) {
+        let mut str = |key: &'static str| diagnostics.value(get_str(data, key));
+        self.exec_name = str("exec");
+        self.ldflags = str("link_flags").or(str("ldflags"));
+        self.compiler = str("compiler");
+        self.c_compiler = str("c_compiler").or(str("cc"));
+        self.cxx_compiler = str("cxx_compiler").or(str("cxx"));
+        self.assembler = str("assembler").or(str("as"));
+        self.archiver = str("archiver").or(str("ar"));
+        self.asflags = str("asm_flags").or(str("asflags"));
+        //compile_flags and cflags are the historical names of extra
+        self.extra = str("extra")
+            .or(str("compile_flags"))
+            .or(str("cflags"));
+
+        self.libs = diagnostics.value(get_str_list(data, "libs"));
+        self.std = diagnostics.value(get_str_list(data, "std"));
+        self.optimize = diagnostics.value(get_str_or_int(data, "optimize")).filter(|level| {
+            let valid = OPTIMIZE_LEVELS.contains(&level.as_str()) || level.starts_with('-');
+            if !valid {
+                diagnostics.error(ContextfulError::from(format!("optimize should be one of {} (got {level})", OPTIMIZE_LEVELS.join(", "))).at_key(data, "optimize"));
+            }
+            valid
+        });
+        self.debug_info = diagnostics.value(get_bool(data, "debug_info"));
+        self.warnings = diagnostics.value(get_str_list(data, "warnings"));
+        self.defines = diagnostics.value(get_str_list(data, "defines"));
     }
 }
 
 impl <'a> Target<'a> {
-    pub fn read(data: &'a Hash) -> Result<Target<'a>, ContentError> {
-        let name = match get_str(data, "name")? {
-            Some(str) => str,
-            None => return Err(ContentError::Other("Targets need a name"))
-        };
+    pub fn read(data: &'a Hash, diagnostics: &mut Diagnostics) -> Option<Target<'a>> {
+        let name = require(get_str(data, "name"), || ContextfulError::from("Targets need a name").at(data), diagnostics)?;
 
         let mut target = Target::new(name);
+        diagnostics.in_context(format!("In target {name}"), |diagnostics| {
+            check_keys(data, &[&TARGET_KEYS, &BUILD_CONFIG_KEYS], diagnostics);
+            target.config.read(data, diagnostics);
+        });
+
+        Some(target)
+    }
+}
+
+impl <'a> Executable<'a> {
+    pub fn read(data: &'a Hash, diagnostics: &mut Diagnostics) -> Option<Executable<'a>> {
+        let name = require(get_str(data, "name"), || ContextfulError::from("Executables need a name").at(data), diagnostics)?;
+
+        diagnostics.in_context(format!("In executable {name}"), |diagnostics| {
+            check_keys(data, &[&EXECUTABLE_KEYS], diagnostics);
+            let main = diagnostics.value(get_str_list(data, "main")).filter(|main| !main.is_empty());
+            if main.is_none() {
+                diagnostics.error(ContextfulError::from("Executables need at least one main source").at_key(data, "main").at(data));
+            }
+            let ldflags = diagnostics.value(get_str(data, "link_flags")).or(diagnostics.value(get_str(data, "ldflags")));
+            let libs = diagnostics.value(get_str_list(data, "libs"));
+
+            Some(Executable {
+                name,
+                main: main?,
+                ldflags,
+                libs
+            })
+        })
+    }
+}
 
-        target.config.read(data).add_context(|| "in target ".to_owned() + name)?;
+impl <'a> TestConfig<'a> {
+    pub fn read(data: &'a Hash, diagnostics: &mut Diagnostics) -> TestConfig<'a> {
+        check_keys(data, &[&TEST_KEYS], diagnostics);
+        let runner = match diagnostics.value(get_str(data, "mode")) {
+            Some("runner") => Some(diagnostics.value(get_str(data, "name")).unwrap_or("tests")),
+            None | Some("per_file") => None,
+            Some(_) => {
+                diagnostics.error(ContextfulError::from("mode should be either per_file or runner").at_key(data, "mode"));
+                None
+            }
+        };
+        let framework = match diagnostics.value(get_str(data, "framework")) {
+            None => None,
+            Some("criterion") => Some(TestFramework::Criterion),
+            Some("gtest" | "googletest") => Some(TestFramework::GoogleTest),
+            Some("catch2") => Some(TestFramework::Catch2),
+            Some(_) => {
+                diagnostics.error(ContextfulError::from("framework should be one of criterion, gtest or catch2").at_key(data, "framework"));
+                None
+            }
+        };
 
-        Ok(target)
+        let mut tests = TestConfig::new(diagnostics.value(get_dir_name(data, "dir")).unwrap_or("tests"));
+        tests.ext = diagnostics.value(get_str_list(data, "ext"));
+        tests.runner = runner;
+        tests.framework = framework;
+        tests.ldflags = diagnostics.value(get_str(data, "link_flags")).or(diagnostics.value(get_str(data, "ldflags")));
+        tests.libs = diagnostics.value(get_str_list(data, "libs"));
+        tests
     }
 }
 
+impl <'a> Profile<'a> {
+    pub fn read(data: &'a Hash, diagnostics: &mut Diagnostics) -> Option<Profile<'a>> {
+        let name = require(get_str(data, "name"), || ContextfulError::from("Profiles need a name").at(data), diagnostics)?;
+
+        let mut profile = Profile::new(name);
+        diagnostics.in_context(format!("In profile {name}"), |diagnostics| {
+            check_keys(data, &[&PROFILE_KEYS, &BUILD_CONFIG_KEYS], diagnostics);
+            profile.obj_dir = diagnostics.value(get_dir_name(data, "obj_dir"));
+            profile.bin_dir = diagnostics.value(get_dir_name(data, "bin_dir"));
+            profile.config.read(data, diagnostics);
+        });
+
+        Some(profile)
+    }
+}
+
+/// Reads each table of a list of tables, recording the entries that aren't tables
+fn read_tables<'a, T>(array: &'a [Yaml], property: &str, diagnostics: &mut Diagnostics, mut read: impl FnMut(&'a Hash, &mut Diagnostics) -> Option<T>) -> Vec<T> {
+    let mut values = Vec::new();
+    for data in array {
+        match data {
+            Yaml::Hash(hash) => values.extend(read(hash, diagnostics)),
+            val => diagnostics.error(handle_wrong_type(val, "table").add_context(format!("{property} property")))
+        }
+    }
+    values
+}
+
 impl <'a> Config<'a> {
-    pub fn read(data: &'a Yaml) -> Result<Config<'a>, ContentError> {
+    /// Reads the config, recording every problem found in `diagnostics` instead of stopping at the first one
+    pub fn read(data: &'a Yaml, diagnostics: &mut Diagnostics) -> Config<'a> {
         let mut config = Config::new();
 
         match &data {
             Yaml::Hash(hash) => {
+                check_keys(hash, &[&CONFIG_KEYS, &BUILD_CONFIG_KEYS], diagnostics);
 
-                if let Some(str) = get_str(hash, "kind")? {
-                    match str {
-                        "cpp" => {
-                            config.default_config.compiler = Some("g++");
-                            config.default_ext = "cpp"
-                        },
-                        "c" => {
-                            config.default_config.compiler = Some("gcc");
-                            config.default_ext = "c";
-                        }
-                        _ => return Err(ContentError::from("Incorrect kind : must be either c or cpp"))
+                //A list of kinds makes a mixed project, the first one being the main language
+                if let Some(kinds) = diagnostics.value(get_str_list(hash, "kind")) {
+                    config.default_ext.clear();
+                    for kind in &kinds {
+                        let lang = match *kind {
+                            "cpp" => Language::Cpp,
+                            "c" => Language::C,
+                            _ => {
+                                diagnostics.error(ContextfulError::from("Incorrect kind : must be either c or cpp, or a list of them").at_key(hash, "kind"));
+                                continue
+                            }
+                        };
+                        if config.default_ext.is_empty() {config.kind = lang}
+                        config.default_ext.push(kind);
+                    }
+                    if kinds.is_empty() {
+                        diagnostics.error(ContextfulError::from("Incorrect kind : the list of kinds is empty").at_key(hash, "kind"));
+                    }
+                    if config.default_ext.is_empty() {
+                        config.default_ext.push("c");
                     }
                 } 
 
@@ -95,54 +241,145 @@ impl <'a> Config<'a> {
                     match yaml {
                         Yaml::Array(arr) => {
                             for yaml in arr {
-                                config.source.push(SourceDir::read_from_hash_or_string(yaml)?);
+                                config.source.extend(SourceDir::read_from_hash_or_string(yaml, diagnostics));
                             }
                         },
                         val => {
-                            config.source.push(SourceDir::read_from_hash_or_string(val)?)
+                            config.source.extend(SourceDir::read_from_hash_or_string(val, diagnostics))
                         }
                     }
-                    if let Some(str) = get_str(hash, "src_ext")?{config.default_ext = str};
-                    if let Some(yaml) = get_data(hash, "src_exclude"){config.exclude_dir = array_or_string_into_vec(yaml)?}
+                    if let Some(exts) = diagnostics.value(get_str_list(hash, "src_ext")) {config.default_ext = exts};
+                    if let Some(exclude) = diagnostics.value(get_str_list(hash, "src_exclude")) {config.exclude_dir = exclude}
                 } else {
                     let Some(source) = config.source.get_mut(0) else {unreachable!("The source vector should never be empty (index 0 should always be valid)")};
-                    if let Some(str) = get_dir_name(hash, "src_dir")? {source.dir = str};
-                    if let Some(yaml) = get_data(hash, "src_exclude"){
-                        source.exclude = array_or_string_into_vec(yaml)?
-                    }
-                    source.depth = get_int(hash, "src_depth")?;
-                    source.ext = get_str(hash, "src_ext")?;
+                    if let Some(str) = diagnostics.value(get_dir_name(hash, "src_dir")) {source.dir = str};
+                    if let Some(exclude) = diagnostics.value(get_str_list(hash, "src_exclude")) {source.exclude = exclude}
+                    source.depth = diagnostics.value(get_int(hash, "src_depth"));
+                    source.ext = diagnostics.value(get_str_list(hash, "src_ext"));
 
                 }
-                if let Some(yaml) = get_data(hash, "include") {
-                    config.include_dir = array_or_string_into_vec(yaml)?;
+                if let Some(include) = diagnostics.value(get_str_list(hash, "include")) {
+                    config.include_dir = include;
                 }
                 for source in &config.source {
                     if source.included {
                         config.include_dir.push(source.dir);
                     }
                 }
-                if let Some(b) = get_bool(hash, "keep_source_dir_names")? {config.keep_source_dir_names = b};
-                if let Some(str) = get_dir_name(hash, "obj_dir")? {config.obj_dir = str};
-                if let Some(str) = get_dir_name(hash, "bin_dir")? {config.bin_dir = str};
-
-                config.default_config.read(hash).add_context(|| "In default config")?;
-                if let Some(array) = get_array(hash, "targets")? {
-                    for data in array {
-                        match data {
-                            Yaml::Hash(hash) => {
-                                config.alt_targets.push(Target::read(hash)?);
-                            },
-                            val => return Err(handle_wrong_type(val, "table").add_context("targets property").into())
+                if let Some(b) = diagnostics.value(get_bool(hash, "keep_source_dir_names")) {config.keep_source_dir_names = b};
+                if let Some(str) = diagnostics.value(get_dir_name(hash, "obj_dir")) {config.obj_dir = str};
+                if let Some(str) = diagnostics.value(get_dir_name(hash, "bin_dir")) {config.bin_dir = str};
+                if let Some(b) = diagnostics.value(get_bool(hash, "depfiles")) {config.depfiles = b};
+                if let Some(b) = diagnostics.value(get_bool(hash, "regenerate")) {config.regenerate = b};
+                match diagnostics.value(get_str(hash, "object_collisions")) {
+                    None => (),
+                    Some("error") => config.object_collisions = CollisionPolicy::Error,
+                    Some("warn") => config.object_collisions = CollisionPolicy::Warn,
+                    Some("disambiguate") => config.object_collisions = CollisionPolicy::Disambiguate,
+                    Some(_) => diagnostics.error(ContextfulError::from("Incorrect object_collisions : must be one of error, warn or disambiguate").at_key(hash, "object_collisions"))
+                }
+                match diagnostics.value(get_str(hash, "source_listing")) {
+                    None => (),
+                    Some("shell") => config.static_listing = false,
+                    Some("static") => config.static_listing = true,
+                    Some(_) => diagnostics.error(ContextfulError::from("Incorrect source_listing : must be either shell or static").at_key(hash, "source_listing"))
+                }
+
+                match diagnostics.value(get_str(hash, "type")) {
+                    None => (),
+                    Some("executable") => config.output_type = OutputType::Executable,
+                    Some("static_lib") => config.output_type = OutputType::StaticLib,
+                    Some("shared_lib") => config.output_type = OutputType::SharedLib,
+                    Some("both") => config.output_type = OutputType::Both,
+                    Some(_) => diagnostics.error(ContextfulError::from("Incorrect type : must be one of executable, static_lib, shared_lib or both").at_key(hash, "type"))
+                }
+                config.lib_name = diagnostics.value(get_str(hash, "lib_name"));
+                config.version = diagnostics.value(get_str_or_int(hash, "version"));
+
+                diagnostics.in_context("In default config", |diagnostics| config.default_config.read(hash, diagnostics));
+                if let Some(array) = diagnostics.value(get_array(hash, "targets")) {
+                    config.alt_targets = read_tables(array, "targets", diagnostics, Target::read);
+                }
+
+                if let Some(array) = diagnostics.value(get_array(hash, "executables")) {
+                    config.executables = read_tables(array, "executables", diagnostics, |hash, diagnostics| {
+                        let exec = Executable::read(hash, diagnostics)?;
+                        for main in &exec.main {
+                            if config.object_path(main).is_none() {
+                                diagnostics.error(ContextfulError::from(format!("Main source {main} is not in any source directory")).add_context(format!("In executable {}", exec.name)).at_key(hash, "main"));
+                            }
                         }
+                        Some(exec)
+                    });
+                }
+
+                config.main = diagnostics.value(get_str_list(hash, "main"));
+                for main in config.main.iter().flatten() {
+                    if config.object_path(main).is_none() {
+                        diagnostics.error(ContextfulError::from(format!("Main source {main} is not in any source directory")).at_key(hash, "main"));
                     }
                 }
 
-                config.output_file = get_str(hash, "output-file")?.or(get_str(hash, "output_file")?);
+                if let Some(yaml) = get_data(hash, "tests") {
+                    config.tests = match yaml {
+                        Yaml::Hash(hash) => Some(diagnostics.in_context("In tests", |diagnostics| TestConfig::read(hash, diagnostics))),
+                        Yaml::String(dir) => Some(TestConfig::new(dir)),
+                        val => {
+                            diagnostics.error(handle_wrong_type(val, "table or string").add_context("tests property"));
+                            None
+                        }
+                    };
+                }
+
+                if let Some(array) = diagnostics.value(get_array(hash, "profiles")) {
+                    let mut names: Vec<&str> = config.alt_targets.iter().map(|target| target.name).collect();
+                    config.profiles = read_tables(array, "profiles", diagnostics, |hash, diagnostics| {
+                        let profile = Profile::read(hash, diagnostics)?;
+                        if names.contains(&profile.name) {
+                            diagnostics.error(ContextfulError::from(format!("Profile name {} is already used by another profile or target", profile.name)).at_key(hash, "name"));
+                        } else if GENERATED_TARGETS.contains(&profile.name) {
+                            diagnostics.error(ContextfulError::from(format!("Profile name {} is a target of the generated Makefile, choose another one", profile.name)).at_key(hash, "name"));
+                        }
+                        names.push(profile.name);
+                        Some(profile)
+                    });
+                }
+                config.default_profile = diagnostics.value(get_str(hash, "default_profile"));
+                if let Some(name) = config.default_profile {
+                    if !config.profiles.iter().any(|p| p.name == name) {
+                        diagnostics.error(ContextfulError::from(format!("Default profile {name} is not declared in profiles")).at_key(hash, "default_profile"));
+                    }
+                }
 
-                return Ok(config);
+                config.name = diagnostics.value(get_str(hash, "name"));
+                config.output_file = diagnostics.value(get_str(hash, "output-file")).or(diagnostics.value(get_str(hash, "output_file")));
             },
-            val => Err(handle_wrong_type(val, "property list").add_context("Config file").into())
+            val => diagnostics.error(handle_wrong_type(val, "property list").add_context("Config file"))
         }
+        config
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::diagnostics::{Diagnostics, Severity};
+    use crate::test_util::yaml;
+
+    fn errors(source: &str) -> Vec<String> {
+        let doc = yaml(source);
+        let mut diagnostics = Diagnostics::new(false);
+        Config::read(&doc, &mut diagnostics);
+        diagnostics.iter().filter(|diag| diag.severity == Severity::Error).map(|diag| diag.error.to_string()).collect()
+    }
+
+    #[test]
+    fn profiles_cant_be_named_after_generated_targets() {
+        for name in ["all", "clean", "distclean", "test"] {
+            let errors = errors(&format!("kind: c\nprofiles:\n  - name: {name}\n"));
+            assert_eq!(errors.len(), 1, "{errors:?}");
+            assert!(errors[0].contains("is a target of the generated Makefile"), "{errors:?}");
+        }
+        assert!(errors("kind: c\nprofiles:\n  - name: debug\n").is_empty());
+    }
+}
@@ -0,0 +1,277 @@
+use std::{collections::HashMap, fmt::Display, fs, io, path::Path};
+
+use super::{CollisionPolicy, Config, SourceDir, TestConfig};
+
+/// Files found in a source directory, and the directories that were looked into
+#[derive(Default)]
+pub struct SourceListing {
+    pub files: Vec<String>,
+    pub dirs: Vec<String>
+}
+
+/// Lists files under `dir` with one of the extensions, matching what the generated
+/// `find dir -maxdepth <depth> -name "*.<ext>" -not -path "*<exclude>*"` command would find
+fn walk(dir: &str, exts: &[&str], depth: Option<i64>, exclude: &[&str]) -> Result<SourceListing, io::Error> {
+    let mut listing = SourceListing::default();
+    //-maxdepth only applies when positive, like in the generated command
+    let max_depth = depth.filter(|depth| *depth > 0);
+    walk_(Path::new(dir), dir.trim_end_matches('/'), 1, max_depth, exts, exclude, &mut listing)
+        .map_err(|err| io::Error::new(err.kind(), format!("couldn't list sources in {dir} : {err}")))?;
+    listing.files.sort();
+    Ok(listing)
+}
+
+fn walk_(dir: &Path, display: &str, depth: i64, max_depth: Option<i64>, exts: &[&str], exclude: &[&str], listing: &mut SourceListing) -> Result<(), io::Error> {
+    if max_depth.is_some_and(|max| depth > max) {
+        return Ok(())
+    }
+    listing.dirs.push(display.to_owned());
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {continue};
+        let path = format!("{display}/{name}");
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            walk_(&entry.path(), &path, depth + 1, max_depth, exts, exclude, listing)?;
+        } else if exts.iter().any(|ext| name.ends_with(&format!(".{ext}")))
+            && !exclude.iter().any(|excl| path.contains(excl)) {
+            listing.files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// A path without its `./` prefixes and trailing `/`, `.` for the current directory
+fn normalize(path: &str) -> &str {
+    let mut path = path.trim_end_matches('/');
+    while let Some(rest) = path.strip_prefix("./") {
+        path = rest.trim_start_matches('/');
+    }
+    if path.is_empty() {"."} else {path}
+}
+
+/// Path of `path` relative to `dir`, if it is inside it. `.` contains every relative path
+fn relative_to<'p>(path: &'p str, dir: &str) -> Option<&'p str> {
+    let (path, dir) = (normalize(path), normalize(dir));
+    if dir == "." {
+        let outside = path == "." || path == ".." || path.starts_with("../") || path.starts_with('/');
+        return if outside {None} else {Some(path)}
+    }
+    path.strip_prefix(dir)?.strip_prefix('/').filter(|rest| !rest.is_empty())
+}
+
+/// Whether a line looks like the definition of a main function (`int main(`, `void main (` ...)
+pub fn defines_main(line: &str) -> bool {
+    let line = line.trim_start();
+    let Some(idx) = line.find("main") else {return false};
+    let (before, after) = (&line[..idx], &line[idx + 4..]);
+    let return_type = before.trim_end();
+    before.ends_with(char::is_whitespace)
+        && ["int", "void", "auto"].iter().any(|ty| return_type == *ty || return_type.ends_with(&format!(" {ty}")))
+        && after.trim_start().starts_with('(')
+}
+
+/// Several sources that would be compiled to the same object file
+pub struct ObjectCollision {
+    object: String,
+    sources: Vec<String>
+}
+
+impl Display for ObjectCollision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sources {} would all be compiled to $(OBJ_DIR)/{}", self.sources.join(", "), self.object)
+    }
+}
+
+impl Config<'_> {
+    fn find_object_collisions(&self) -> Vec<ObjectCollision> {
+        let mut objects = HashMap::<String, Vec<String>>::new();
+        for source in &self.source {
+            let dir = source.dir.trim_end_matches('/');
+            //a missing source directory is just empty for the generated find command too
+            for file in self.list_sources(source).unwrap_or_default().files {
+                let rest = file.strip_prefix(&format!("{dir}/")).unwrap_or(&file);
+                let sources = objects.entry(self.object_name(dir, rest)).or_default();
+                if !sources.contains(&file) {
+                    sources.push(file);
+                }
+            }
+        }
+        let mut collisions: Vec<ObjectCollision> = objects.into_iter()
+            .filter(|(_, sources)| sources.len() > 1)
+            .map(|(object, sources)| ObjectCollision{object, sources})
+            .collect();
+        collisions.sort_by(|a, b| a.object.cmp(&b.object));
+        collisions
+    }
+
+    /// Checks that no two sources are compiled to the same object, moving objects to subfolders named after
+    /// their source directory if the config allows it. Returns the collisions that are only worth a warning,
+    /// or the blocking ones with a hint on how to fix them
+    pub fn check_object_collisions(&mut self) -> Result<Vec<ObjectCollision>, (Vec<ObjectCollision>, &'static str)> {
+        let mut collisions = self.find_object_collisions();
+        if !collisions.is_empty() && self.object_collisions == CollisionPolicy::Disambiguate && !self.keep_source_dir_names {
+            self.keep_source_dir_names = true;
+            //sources of a same directory differing only by their extension still collide
+            collisions = self.find_object_collisions();
+        }
+        match self.object_collisions {
+            CollisionPolicy::Warn => Ok(collisions),
+            _ if collisions.is_empty() => Ok(collisions),
+            _ if self.keep_source_dir_names => Err((collisions, "Sources of a same directory need different names, regardless of their extension")),
+            _ => Err((collisions, "Set object_collisions to disambiguate (or keep_source_dir_names to true) to put objects in per-source-directory folders"))
+        }
+    }
+
+    /// The test directory as found when listing a source directory that contains it (e.g. `./tests` in `.`),
+    /// for its sources to be left out of the project
+    pub(super) fn tests_inside(&self, source: &SourceDir) -> Option<String> {
+        let rest = relative_to(self.tests.as_ref()?.dir, source.dir)?;
+        Some(format!("{}/{rest}", source.dir.trim_end_matches('/')))
+    }
+
+    /// Source files of a source directory, found at generation time
+    pub(super) fn list_sources(&self, source: &SourceDir) -> Result<SourceListing, io::Error> {
+        let mut exclude = source.exclude.clone();
+        exclude.extend(&self.exclude_dir);
+        let mut listing = walk(source.dir, self.source_exts(source), source.depth, &exclude)?;
+        //test sources living inside a source directory are not part of the project
+        if let Some(test_dir) = self.tests_inside(source) {
+            let prefix = format!("{test_dir}/");
+            listing.files.retain(|file| !file.starts_with(&prefix));
+            listing.dirs.retain(|dir| *dir != test_dir && !dir.starts_with(&prefix));
+        }
+        Ok(listing)
+    }
+
+    /// Test source files, found at generation time
+    pub(super) fn list_test_sources(&self, tests: &TestConfig) -> Result<SourceListing, io::Error> {
+        walk(tests.dir, self.test_exts(tests), None, &[])
+    }
+
+    /// Source files of every source directory, as the Makefile would find them
+    pub fn source_files(&self) -> Result<Vec<String>, io::Error> {
+        let mut files = Vec::new();
+        for source in &self.source {
+            for file in self.list_sources(source)?.files {
+                if !files.contains(&file) {
+                    files.push(file);
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// Test source files, none if the config has no tests
+    pub fn test_source_files(&self) -> Result<Vec<String>, io::Error> {
+        match &self.tests {
+            Some(tests) => Ok(self.list_test_sources(tests)?.files),
+            None => Ok(Vec::new())
+        }
+    }
+
+    /// Source files containing a main function, for projects that don't declare their entry points
+    pub(super) fn find_main_sources(&self) -> Result<Vec<String>, io::Error> {
+        let mut mains = Vec::new();
+        for source in &self.source {
+            for file in self.list_sources(source)?.files {
+                //unreadable (e.g. non UTF-8) files are not worth failing the generation for
+                if fs::read_to_string(&file).is_ok_and(|content| content.lines().any(defines_main)) {
+                    mains.push(file);
+                }
+            }
+        }
+        Ok(mains)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::config::Config;
+    use crate::diagnostics::Diagnostics;
+    use crate::test_util::{render, temp_dir, yaml};
+    use super::{defines_main, relative_to};
+
+    /// Objects the sources of `dirs` collide on with the given policy, with the hint if they are an error
+    fn collisions(dirs: &[String], policy: &str) -> (Vec<String>, Option<&'static str>) {
+        let doc = yaml(&format!("kind: c\nsrc_ext: [c, cpp]\nsources: [{}]\nobject_collisions: {policy}\n", dirs.join(", ")));
+        let mut diagnostics = Diagnostics::new(true);
+        let mut config = Config::read(&doc, &mut diagnostics);
+        assert!(!diagnostics.has_errors());
+        let (collisions, hint) = match config.check_object_collisions() {
+            Ok(collisions) => (collisions, None),
+            Err((collisions, hint)) => (collisions, Some(hint))
+        };
+        (collisions.into_iter().map(|collision| collision.object).collect(), hint)
+    }
+
+    #[test]
+    fn paths_inside_a_directory() {
+        assert_eq!(relative_to("tests", "."), Some("tests"));
+        assert_eq!(relative_to("./tests/", "./"), Some("tests"));
+        assert_eq!(relative_to("src/tests", "./src"), Some("tests"));
+        assert_eq!(relative_to("srcs/tests", "src"), None);
+        assert_eq!(relative_to("src", "src"), None);
+        assert_eq!(relative_to(".", "."), None);
+        assert_eq!(relative_to("../tests", "."), None);
+    }
+
+    #[test]
+    fn main_definitions() {
+        assert!(defines_main("int main(int argc, char** argv) {"));
+        assert!(defines_main("static int main ()"));
+        assert!(!defines_main("int domain(void);"));
+        assert!(!defines_main("// main() is below"));
+    }
+
+    #[test]
+    fn tests_in_current_directory_are_excluded() {
+        let makefile = render("kind: c\nsources: .\ntests:\n  dir: tests\n");
+        assert!(makefile.contains("-not -path \"./tests/*\""), "{makefile}");
+    }
+
+    #[test]
+    fn static_listing_leaves_out_tests() {
+        let root = temp_dir("sources");
+        fs::create_dir_all(root.join("tests")).unwrap();
+        fs::write(root.join("main.c"), "int main() {}\n").unwrap();
+        fs::write(root.join("tests/test_add.c"), "int main() {}\n").unwrap();
+        let root = root.display().to_string();
+
+        let yaml = format!("kind: c\nsources: {root}\ntests:\n  dir: {root}/tests/\n");
+        let doc = self::yaml(&yaml);
+        let mut diagnostics = Diagnostics::new(true);
+        let config = Config::read(&doc, &mut diagnostics);
+        let sources = config.source_files();
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(sources.unwrap(), vec![format!("{root}/main.c")]);
+    }
+
+    #[test]
+    fn object_collisions() {
+        let root = temp_dir("collisions");
+        for file in ["a/util.c", "a/main.c", "b/util.c", "c/x.c", "c/x.cpp"] {
+            fs::create_dir_all(root.join(file).parent().unwrap()).unwrap();
+            fs::write(root.join(file), "").unwrap();
+        }
+        let dir = |name: &str| root.join(name).display().to_string();
+        let (a_b, c) = ([dir("a"), dir("b")], [dir("c")]);
+
+        let error = collisions(&a_b, "error");
+        let warn = collisions(&a_b, "warn");
+        let disambiguate = collisions(&a_b, "disambiguate");
+        let same_dir = collisions(&c, "disambiguate");
+        let _ = fs::remove_dir_all(&root);
+
+        assert_eq!(error.0, vec!["util.o"]);
+        assert!(error.1.is_some_and(|hint| hint.contains("disambiguate")));
+        assert_eq!(warn, (vec!["util.o".to_owned()], None));
+        assert_eq!(disambiguate, (Vec::new(), None));
+        assert_eq!(same_dir.0, vec![format!("{}/x.o", c[0])]);
+        assert!(same_dir.1.is_some_and(|hint| hint.contains("regardless of their extension")));
+    }
+}
@@ -1,32 +1,40 @@
 use core::str;
 use std::{collections::HashSet, fs::File, io::{self, Write}};
 
-use super::{init_default, Config, Target};
+use super::{init_default, BuildConfig, Config, Invocation, Language, OutputType, Profile, Target, TestConfig, TestFramework, STDIO};
 
-fn nl(file: &mut File) -> Result<(), io::Error> {
-    file.write(b"\n")?;
+fn nl(file: &mut impl Write) -> Result<(), io::Error> {
+    file.write_all(b"\n")?;
     Ok(())
 }
 
-fn writeln(file: &mut File, str: &str) -> Result<(), io::Error>{
-    file.write(str.as_bytes())?;
+fn writeln(file: &mut impl Write, str: &str) -> Result<(), io::Error>{
+    file.write_all(str.as_bytes())?;
     nl(file)?;
     Ok(())
 }
 
-fn write_var(file: &mut File, varname: &[u8], val: &str) -> Result<(), io::Error> {
-    file.write(varname)?;
-    file.write(b":=")?;
+fn write_var(file: &mut impl Write, varname: &[u8], val: &str) -> Result<(), io::Error> {
+    file.write_all(varname)?;
+    file.write_all(b":=")?;
     writeln(file, val)?;
     Ok(())
 }
 
-fn concat_str<'a, T: ToString>(str1: &'static str, str2: T) -> String {
+/// Writes a recursively expanded variable (`=`), for variables composed of other variables
+fn write_rvar(file: &mut impl Write, varname: &[u8], val: &str) -> Result<(), io::Error> {
+    file.write_all(varname)?;
+    file.write_all(b"=")?;
+    writeln(file, val)?;
+    Ok(())
+}
+
+fn concat_str<T: ToString>(str1: &'static str, str2: T) -> String {
     str1.to_owned() + (&str2.to_string())
 }
 
-fn concat_str_post<'a, T: ToString>(str1: T, str2: &'static str) -> String {
-    (str1.to_string()) + &str2.to_owned()
+fn concat_str_post<T: ToString>(str1: T, str2: &'static str) -> String {
+    (str1.to_string()) + str2
 }
 
 fn string_if<F: Fn()->String>(cond: bool, expr: F) -> String{
@@ -44,16 +52,91 @@ fn string_if_option<T, F: Fn(T)->String>(opt: Option<T>, expr: F) -> String{
     }
 }
 
+/// Replaces a leading `+` with a reference to the variable the value is assigned to, appending to the
+/// inherited value. `+` elsewhere is part of the value (`-std=c++17`, `-lstdc++`)
+fn expand_plus(val: &str, varname: &str) -> String {
+    match val.strip_prefix('+') {
+        Some(rest) => format!("$({varname}) {}", rest.trim_start()).trim_end().to_owned(),
+        None => val.to_owned()
+    }
+}
+
+/// Builds a list of flags, prefixing each value unless it is already a raw flag (starting with `-`).
+/// A `+` value stands for the previous value of the variable.
+fn flag_list(values: &[&str], prefix: &str, varname: &str) -> String {
+    let mut str = String::new();
+    for val in values {
+        match *val {
+            "+" => str += &format!("$({varname}) "),
+            val if val.starts_with('-') => str += &concat_str_post(val, " "),
+            val => str += &format!("{prefix}{val} ")
+        }
+    }
+    str
+}
+
+/// Variables holding the structured compile flags, in the order they appear in CFLAGS/CXXFLAGS
+const FLAG_VARS: [&str; 7] = ["CSTD", "CXXSTD", "OPTFLAGS", "DEBUGFLAGS", "WARNFLAGS", "DEFINES", "EXTRAFLAGS"];
+
+impl BuildConfig<'_> {
+    /// Compiler set for a language, `compiler` standing for the one of the project kind
+    pub(super) fn compiler_for(&self, lang: Language, kind: Language) -> Option<&str> {
+        let specific = match lang {
+            Language::C => self.c_compiler,
+            Language::Cpp => self.cxx_compiler,
+            Language::Asm => self.assembler
+        };
+        specific.or(if lang == kind {self.compiler} else {None})
+    }
+
+    /// Values of the structured flag variables that are set in this config. `inherits` is false for
+    /// the default config, that has no value to append to
+    fn flag_vars(&self, inherits: bool) -> Vec<(&'static str, String)> {
+        let mut vars = Vec::new();
+        if let Some(std) = &self.std {
+            let (cxx_std, c_std): (Vec<&str>, Vec<&str>) = std.iter().partition(|std| std.contains("++"));
+            if !c_std.is_empty() {vars.push(("CSTD", flag_list(&c_std, "-std=", "CSTD")))}
+            if !cxx_std.is_empty() {vars.push(("CXXSTD", flag_list(&cxx_std, "-std=", "CXXSTD")))}
+        }
+        if let Some(level) = &self.optimize {
+            vars.push(("OPTFLAGS", if level.starts_with('-') {level.clone()} else {concat_str("-O", level)}));
+        }
+        if let Some(debug_info) = self.debug_info {
+            vars.push(("DEBUGFLAGS", string_if(debug_info, || "-g".to_owned())));
+        }
+        if let Some(warnings) = &self.warnings {
+            let warnings: Vec<&str> = warnings.iter().map(|w| if *w == "none" {"-w"} else {w}).collect();
+            vars.push(("WARNFLAGS", flag_list(&warnings, "-W", "WARNFLAGS")));
+        }
+        if let Some(defines) = &self.defines {
+            vars.push(("DEFINES", flag_list(defines, "-D", "DEFINES")));
+        }
+        if let Some(extra) = self.extra {
+            vars.push(("EXTRAFLAGS", if inherits {expand_plus(extra, "EXTRAFLAGS")} else {extra.to_owned()}));
+        }
+        vars
+    }
+}
+
 impl Config <'_>{
-    pub fn write(&self, filename: &str){
-        match self.write_(filename) {
-            Err(msg) => println!("Couldn't write Makefile at path {filename} : {msg}"),
-            _ => println!("Successfully wrote config to {filename}")
+    pub fn write(&self, filename: &str, invocation: &Invocation) -> Result<(), io::Error>{
+        if filename == STDIO {
+            let mut out = io::stdout().lock();
+            self.write_to(&mut out, filename, invocation)?;
+            out.flush()
+        } else {
+            self.write_to(&mut File::create(filename)?, filename, invocation)
         }
     }
 
-    fn write_(&self, filename: &str) -> Result<(), io::Error>{
-        let mut file = File::create(filename)?;
+    /// The Makefile that would be written at `filename`
+    pub fn render(&self, filename: &str, invocation: &Invocation) -> Result<String, io::Error> {
+        let mut out = Vec::new();
+        self.write_to(&mut out, filename, invocation)?;
+        String::from_utf8(out).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    fn write_to(&self, file: &mut impl Write, filename: &str, invocation: &Invocation) -> Result<(), io::Error>{
 
         let defaults = init_default();
 
@@ -64,197 +147,559 @@ impl Config <'_>{
         }
 
         //--- Variables
-        write_var(&mut file, b"CC", or_default!(self.default_config, compiler))?;
-        write_var(&mut file, b"EXEC", or_default!(self.default_config, exec_name))?;
-        //write_var(&mut file, b"INCLUDE_DIR", self.include_dir)?;
-        //write_var(&mut file, b"SRC_DIR", self.src_dir)?;
-        write_var(&mut file, b"OBJ_DIR", self.obj_dir)?;
-        write_var(&mut file, b"BIN_DIR", self.bin_dir)?;
-
-        //write_var(&mut file, b"SRC_EXT", self.src_ext)?;
-        write_var(&mut file, b"CFLAGS", or_default!(self.default_config, cflags))?;
-        write_var(&mut file, b"LDFLAGS", or_default!(self.default_config, ldflags))?;
+        //Captured first, before any other makefile gets included
+        write_var(file, b"THIS_MAKEFILE", "$(lastword $(MAKEFILE_LIST))")?;
+        //Environment variables the config refers to, unless set when make runs
+        for (name, default) in &self.env_defaults {
+            writeln(file, &format!("{name}?={default}"))?;
+        }
+        let compiler = |lang: Language, default: &'static str| self.default_config.compiler_for(lang, self.kind).unwrap_or(default);
+        write_var(file, b"CC", compiler(Language::C, defaults.c_compiler))?;
+        write_var(file, b"CXX", compiler(Language::Cpp, defaults.cxx_compiler))?;
+        let uses_asm = self.uses_language(Language::Asm) || self.default_config.compiler_for(Language::Asm, self.kind).is_some();
+        if uses_asm {
+            write_var(file, b"AS", compiler(Language::Asm, defaults.assembler))?;
+        }
+        //Linking C++ objects needs the C++ runtime, that only the C++ driver adds
+        write_var(file, b"LINKER", if self.uses_language(Language::Cpp) {"$(CXX)"} else {"$(CC)"})?;
+        if self.output_type == OutputType::Executable {
+            if self.executables.is_empty() {
+                write_var(file, b"EXEC", or_default!(self.default_config, exec_name))?;
+            }
+        } else {
+            write_var(file, b"LIB_NAME", self.lib_name.unwrap_or(or_default!(self.default_config, exec_name)))?;
+        }
+        if self.output_type.static_lib() {
+            write_var(file, b"AR", or_default!(self.default_config, archiver))?;
+        }
+        if let (true, Some(version)) = (self.output_type.shared_lib(), &self.version) {
+            write_var(file, b"VERSION", version)?;
+            write_var(file, b"SONAME", &format!("lib$(LIB_NAME).so.{}", version.split('.').next().unwrap_or(version)))?;
+        }
+        //write_var(file, b"INCLUDE_DIR", self.include_dir)?;
+        //write_var(file, b"SRC_DIR", self.src_dir)?;
+        write_var(file, b"OBJ_DIR", self.obj_dir)?;
+        write_var(file, b"BIN_DIR", self.bin_dir)?;
+
+        //write_var(file, b"SRC_EXT", self.src_ext)?;
+        write_var(file, b"LDFLAGS", or_default!(self.default_config, ldflags))?;
+        if self.depfiles {
+            write_var(file, b"DEPFLAGS", "-MMD -MP")?;
+        }
 
         //--- Libs
         let libs = or_default!(self.default_config, libs);
-        file.write(b"LIBS=")?;
+        file.write_all(b"LIBS=")?;
         for lib in libs {
-            file.write(b"-l")?;
-            file.write(lib.as_bytes())?;
-            file.write(b" ")?;
+            file.write_all(b"-l")?;
+            file.write_all(lib.as_bytes())?;
+            file.write_all(b" ")?;
         }
-        nl(&mut file)?;
+        nl(file)?;
 
         //--- Include path
-        file.write(b"INCLUDE=")?;
+        file.write_all(b"INCLUDE=")?;
         for dir in &self.include_dir {
-            file.write(b"-I")?;
-            file.write(dir.as_bytes())?;
-            file.write(b" ")?;
+            file.write_all(b"-I")?;
+            file.write_all(dir.as_bytes())?;
+            file.write_all(b" ")?;
         }
-        nl(&mut file)?;
+        nl(file)?;
 
-        nl(&mut file)?;
+        //--- Compile flags
+        let flag_vars = self.default_config.flag_vars(false);
+        for varname in FLAG_VARS {
+            let val = flag_vars.iter().find(|(name, _)| *name == varname).map_or("", |(_, val)| val.as_str());
+            write_var(file, varname.as_bytes(), val.trim_end())?;
+        }
+        //Objects of a shared library must be position independent
+        let pic = if self.output_type.shared_lib() {
+            write_var(file, b"PICFLAGS", "-fPIC")?;
+            " $(PICFLAGS)"
+        } else {""};
+        write_rvar(file, b"CPPFLAGS", "$(INCLUDE) $(DEFINES)")?;
+        write_rvar(file, b"CFLAGS", &format!("$(CSTD) $(OPTFLAGS) $(DEBUGFLAGS) $(WARNFLAGS) $(EXTRAFLAGS){pic}"))?;
+        write_rvar(file, b"CXXFLAGS", &format!("$(CXXSTD) $(OPTFLAGS) $(DEBUGFLAGS) $(WARNFLAGS) $(EXTRAFLAGS){pic}"))?;
+        if uses_asm {
+            write_var(file, b"ASFLAGS", or_default!(self.default_config, asflags))?;
+        }
+
+        //--- Profiles, selected with make PROFILE=<name>
+        if !self.profiles.is_empty() {
+            let default_profile = self.default_profile.unwrap_or(self.profiles[0].name);
+            nl(file)?;
+            //a plain assignment is only overridden from the command line, not by a PROFILE environment variable
+            writeln(file, &format!("PROFILE:={default_profile}"))?;
+            writeln(file, &format!("PROFILES:={}", self.profiles.iter().map(|p| p.name).collect::<Vec<_>>().join(" ")))?;
+            //cleaning works whatever the profile
+            write!(file, "ifneq ($(filter-out clean clean-profile distclean clear,$(or $(MAKECMDGOALS),all)),)
+ifeq ($(filter $(PROFILE),$(PROFILES)),)
+$(error Unknown profile \"$(PROFILE)\" (available profiles : $(PROFILES)))
+endif
+endif
+")?;
+            for profile in &self.profiles {
+                profile.write_conditional(file, self)?;
+            }
+        }
+
+        nl(file)?;
 
         //--- Processing sources into objs
-        file.write(b"OBJS=")?;
+        let mut watched_dirs = Vec::new();
+        file.write_all(b"OBJS=")?;
         for source in &self.source {
-            let ext = if let Some(ext) = source.ext {ext} else {self.default_ext};
             let dir = source.dir;
 
+            let sources = if self.static_listing {
+                let listing = self.list_sources(source)?;
+                watched_dirs.extend(listing.dirs);
+                static_list(&listing.files)
+            } else {
+                format!("$(shell find {dir}{} {} {})",
+                    string_if_option(source.depth, |depth: i64| string_if(depth > 0, || concat_str(" -maxdepth ", depth))),
+                    find_names(self.source_exts(source)),
+                    {
+                        let mut str = String::new();
+                        for path in &source.exclude {
+                            str += format!("-not -path \"*{}*\" ", path).as_str();
+                        }
+                        for path in &self.exclude_dir {
+                            str += format!("-not -path \"*{}*\" ", path).as_str();
+                        }
+                        //test sources living inside a source directory are not part of the project
+                        if let Some(test_dir) = self.tests_inside(source) {
+                            str += format!("-not -path \"{test_dir}/*\" ").as_str();
+                        }
+                        str
+                    }
+                )
+            };
+
             write!(file, "
-_SRC= $(shell find {dir}{} -name \"*.{ext}\" {})
-_OBJS= $(_SRC:.{ext}=.o)
+_SRC= {sources}
+_OBJS= $(addsuffix .o,$(basename $(_SRC)))
 OBJS := $(OBJS) $(patsubst {dir}/%,$(OBJ_DIR)/{}%,$(_OBJS))
                 ", 
-                string_if_option(source.depth, |depth: i64| string_if(depth > 0, || concat_str(" -maxdepth ", depth))),
-                {
-                    let mut str = String::new();
-                    for path in &source.exclude {
-                        str += format!("-not -path \"*{}*\" ", path).as_str();
-                    }
-                    for path in &self.exclude_dir {
-                        str += format!("-not -path \"*{}*\" ", path).as_str();
-                    }
-                    str
-                },
                 string_if(self.keep_source_dir_names, || concat_str_post(dir, "/")),
             )?;
 
         }
-        nl(&mut file)?;
+        nl(file)?;
 
         //--- Vpath
         for source in &self.source {
-            file.write(b"vpath %.")?;
-            file.write((if let Some(ext) = source.ext {ext} else {self.default_ext}).as_bytes())?;
-            file.write(b" ")?;
-            file.write(source.dir.as_bytes())?;
+            for ext in self.source_exts(source) {
+                writeln(file, &format!("vpath %.{ext} {}", source.dir))?;
+            }
         }
-        nl(&mut file)?;
+
+        //--- Outputs (recursive, as BIN_DIR may be changed by the selected profile)
+        nl(file)?;
+        let mut outputs = Vec::new();
+        if !self.executables.is_empty() || self.tests.is_some() {
+            //Entry points are left out of the shared objects, each executable (or test) adding its own
+            write_rvar(file, b"MAIN_OBJS", &self.main_objects().join(" "))?;
+            write_rvar(file, b"COMMON_OBJS", "$(filter-out $(MAIN_OBJS),$(OBJS))")?;
+        }
+        for exec in &self.executables {
+            outputs.push(format!("$(BIN_DIR)/{}", exec.name));
+        }
+        if self.executables.is_empty() && self.output_type == OutputType::Executable {
+            outputs.push("$(BIN_DIR)/$(EXEC)".to_owned());
+        }
+        if self.output_type.static_lib() {
+            write_rvar(file, b"STATIC_LIB", "$(BIN_DIR)/lib$(LIB_NAME).a")?;
+            outputs.push("$(STATIC_LIB)".to_owned());
+        }
+        if self.output_type.shared_lib() {
+            write_rvar(file, b"SHARED_LIB", "$(BIN_DIR)/lib$(LIB_NAME).so")?;
+            outputs.push("$(SHARED_LIB)".to_owned());
+        }
+        write_rvar(file, b"OUTPUTS", &outputs.join(" "))?;
 
         //--- Static boilerplate (Phony, all, start/mkdir, clear)
         write!(file, "
 .PHONY: start
 
-all: start $(EXEC)
+all: start $(OUTPUTS)
 
 start:
-	mkdir -p $(OBJ_DIR)
-
-$(BIN_DIR)/$(EXEC): $(OBJS)
-\t$(CC) $^ -o $@ $(LDFLAGS) $(LIBS)
-
+	mkdir -p $(OBJ_DIR) $(BIN_DIR)
 ")?;
+        self.write_link_rules(file)?;
 
         if self.keep_source_dir_names {
             let mut exts = HashSet::<String>::new();
             for source in &self.source {
-                let ext = match source.ext {
-                    Some(str) => str,
-                    None => self.default_ext
-                };
-                
-                if !exts.contains(ext) {
-                    exts.insert(ext.to_owned());
-                    write!(file, "
-$(OBJ_DIR)/%.o: %.{ext}
-\t@mkdir -p $(dir $@)
-\t$(CC) $(INCLUDE) -c $< -o $@
-                        ",
-                    )?;
+                for ext in self.source_exts(source) {
+                    if !exts.contains(*ext) {
+                        exts.insert(ext.to_string());
+                        self.write_object_rule(file, "", "", ext)?;
+                    }
                 }
             }
         } else {
             for source in &self.source {
+                for ext in self.source_exts(source) {
+                    self.write_object_rule(file, "", &concat_str_post(source.dir, "/"), ext)?;
+                }
+            }
+        }
+
+        if let Some(tests) = &self.tests {
+            self.write_tests(file, tests, &mut watched_dirs)?;
+        }
+
+        for target in &self.alt_targets {
+            target.write(file, self.kind)?;
+        }
+
+        for profile in &self.profiles {
+            write!(file, "
+.PHONY: {0}
+{0}:
+\t@$(MAKE) --no-print-directory PROFILE={0}
+", profile.name)?;
+        }
+
+        //--- Header dependencies generated by the compiler (-MMD), one .d next to each .o
+        if self.depfiles {
+            write!(file, "
+-include $(OBJS:.o=.d){}
+",
+                string_if(self.tests.is_some(), || " $(TEST_OBJS:.o=.d)".to_owned())
+            )?;
+        }
+
+        //a Makefile written to stdout or from a config read on stdin can't be regenerated
+        if self.regenerate && filename != STDIO && invocation.input_file != STDIO {
+            //with static listing, adding or removing a source updates its directory, so the Makefile gets outdated
+            write_regeneration_rule(file, invocation, &watched_dirs)?;
+        }
+
+        self.write_clean_rules(file)?;
+
+        Ok(())
+    }
+
+    /// Writes clean (build products of the config only), distclean (also the Makefile) and clear (alias of clean)
+    fn write_clean_rules(&self, file: &mut impl Write) -> Result<(), io::Error> {
+        let mut outputs = vec!["$(OUTPUTS)".to_owned()];
+        if self.output_type.shared_lib() && self.version.is_some() {
+            outputs.push("$(SHARED_LIB).$(VERSION) $(BIN_DIR)/$(SONAME)".to_owned());
+        }
+        for target in &self.alt_targets {
+            if let Some(exec_name) = target.config.exec_name {
+                outputs.push(concat_str("$(BIN_DIR)/", exec_name));
+            }
+        }
+        if self.tests.is_some() {
+            outputs.push("$(TEST_BINS)".to_owned());
+        }
+
+        //Objects are only looked for in OBJ_DIR, except when it is the project root (then only ours are removed)
+        let mut obj_dirs = std::iter::once(self.obj_dir).chain(self.profiles.iter().filter_map(|profile| profile.obj_dir));
+        let objs = if obj_dirs.any(|dir| dir == ".") {
+            format!("-rm -f $(OBJS) $(OBJS:.o=.d){}", string_if(self.tests.is_some(), || " $(TEST_OBJS) $(TEST_OBJS:.o=.d)".to_owned()))
+        } else {
+            "find $(OBJ_DIR) \\( -name '*.o' -o -name '*.d' \\) -delete 2> /dev/null || true
+\tfind $(OBJ_DIR) -depth -type d -empty -delete 2> /dev/null || true".to_owned()
+        };
+        //BIN_DIR is only removed if nothing else is left in it
+        let recipe = format!("\t-rm -f {}\n\t{objs}\n{}",
+            outputs.join(" "),
+            string_if(self.bin_dir != "." || !self.profiles.is_empty(), || "\t@rmdir -p $(BIN_DIR) 2> /dev/null || true\n".to_owned())
+        );
+
+        writeln(file, "\n.PHONY: clean distclean clear")?;
+        if self.profiles.is_empty() {
+            write!(file, "clean:\n{recipe}")?;
+        } else {
+            //each profile has its own directories, cleaned by a make invocation with that profile
+            writeln(file, "clean:")?;
+            for profile in &self.profiles {
+                writeln(file, &format!("\t@$(MAKE) --no-print-directory clean-profile PROFILE={}", profile.name))?;
+            }
+            write!(file, "\n.PHONY: clean-profile\nclean-profile:\n{recipe}")?;
+        }
+        write!(file, "
+distclean: clean
+\t-rm -f $(THIS_MAKEFILE)
+
+clear: clean
+")
+    }
+
+    /// Writes the rules producing the outputs from the objects
+    fn write_link_rules(&self, file: &mut impl Write) -> Result<(), io::Error> {
+        for exec in &self.executables {
+            let output = format!("$(BIN_DIR)/{}", exec.name);
+            let main_objs: Vec<String> = exec.main.iter().filter_map(|main| self.object_path(main)).collect();
+            nl(file)?;
+            //Only link flags are scoped to the executable, as compile flags would leak to the shared objects
+            write_target_var_with_expansion(file, b"LDFLAGS", exec.ldflags, Some(&output))?;
+            if let Some(libs) = &exec.libs {
+                write_target_var_(file, b"LIBS", flag_list(libs, "-l", "LIBS").trim_end(), Some(&output))?;
+            }
+            write!(file, "{output}: $(COMMON_OBJS) {}
+\t$(LINKER) $^ -o $@ $(LDFLAGS) $(LIBS)
+", main_objs.join(" "))?;
+        }
+        //Libraries only contain the shared objects when the project also has executables
+        let objs = if self.executables.is_empty() {"$(OBJS)"} else {"$(COMMON_OBJS)"};
+        if self.executables.is_empty() && self.output_type == OutputType::Executable {
+            write!(file, "
+$(BIN_DIR)/$(EXEC): $(OBJS)
+\t$(LINKER) $^ -o $@ $(LDFLAGS) $(LIBS)
+")?;
+        }
+        if self.output_type.static_lib() {
+            write!(file, "
+$(STATIC_LIB): {objs}
+\t$(AR) rcs $@ $^
+")?;
+        }
+        if self.output_type.shared_lib() {
+            if let Some(version) = &self.version {
+                //Real file is libname.so.x.y.z, with the soname and the plain .so as symlinks to it
                 write!(file, "
-$(OBJ_DIR)/%.o: {}/%.{}
-\t@mkdir -p $(dir $@)
-\t$(CC) $(INCLUDE) -c $< -o $@
-                    ",
-                    source.dir,
-                    if let Some(ext) = source.ext {ext} else {self.default_ext}
+$(SHARED_LIB).$(VERSION): {objs}
+\t$(LINKER) -shared -Wl,-soname,$(SONAME) $^ -o $@ $(LDFLAGS) $(LIBS)
+
+$(SHARED_LIB): $(SHARED_LIB).$(VERSION)
+{}\tln -sf $(notdir $<) $@
+",
+                    //with a major-only version, the soname is the real file
+                    string_if(version.contains('.'), || "\tln -sf $(notdir $<) $(BIN_DIR)/$(SONAME)\n".to_owned())
                 )?;
+            } else {
+                write!(file, "
+$(SHARED_LIB): {objs}
+\t$(LINKER) -shared $^ -o $@ $(LDFLAGS) $(LIBS)
+")?;
             }
         }
+        nl(file)
+    }
 
-        for target in &self.alt_targets {
-            target.write(&mut file)?;
+    /// Objects of the entry points : those of the declared executables or main sources,
+    /// or the sources found to define a main function
+    fn main_objects(&self) -> Vec<String> {
+        let mains: Vec<String> = if !self.executables.is_empty() {
+            self.executables.iter().flat_map(|exec| exec.main.iter().map(|main| main.to_string())).collect()
+        } else if let Some(main) = &self.main {
+            main.iter().map(|main| main.to_string()).collect()
+        } else if self.output_type == OutputType::Executable {
+            //an unreadable source directory is also empty for the generated find command
+            self.find_main_sources().unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        mains.iter().filter_map(|main| self.object_path(main)).collect()
+    }
+
+    /// Writes the test executables and the test / test-<name> targets running them
+    fn write_tests(&self, file: &mut impl Write, tests: &TestConfig, watched_dirs: &mut Vec<String>) -> Result<(), io::Error> {
+        let dir = tests.dir;
+        let exts = self.test_exts(tests);
+        let sources = if self.static_listing {
+            let listing = self.list_test_sources(tests)?;
+            watched_dirs.extend(listing.dirs);
+            static_list(&listing.files)
+        } else {
+            format!("$(shell find {dir} {})", find_names(exts))
+        };
+
+        let mut libs: Vec<&str> = tests.framework.map_or(&[][..], TestFramework::libs).to_vec();
+        libs.extend(tests.libs.iter().flatten());
+        let ldflags = [tests.framework.map_or("", TestFramework::ldflags), tests.ldflags.unwrap_or("")];
+        //C++ tests of a C project still need the C++ runtime
+        let cpp = tests.framework.is_some_and(TestFramework::is_cpp) || exts.iter().any(|ext| self.language_of(ext) == Language::Cpp);
+        let linker = if cpp {"$(CXX)"} else {"$(LINKER)"};
+
+        write!(file, "
+#--- Tests
+TEST_SRC:={sources}
+TEST_OBJS:=$(patsubst {dir}/%,$(OBJ_DIR)/{dir}/%,$(addsuffix .o,$(basename $(TEST_SRC))))
+TEST_BIN_DIR:=$(OBJ_DIR)/test_bin
+TEST_LDFLAGS:={}
+TEST_LIBS:={}
+.SECONDARY: $(TEST_OBJS)
+",
+            ldflags.iter().filter(|flags| !flags.is_empty()).copied().collect::<Vec<_>>().join(" "),
+            flag_list(&libs, "-l", "TEST_LIBS").trim_end()
+        )?;
+
+        match tests.runner {
+            None => write!(file, "TEST_BINS:=$(patsubst $(OBJ_DIR)/{dir}/%.o,$(TEST_BIN_DIR)/%,$(TEST_OBJS))
+
+$(TEST_BIN_DIR)/%: $(OBJ_DIR)/{dir}/%.o $(COMMON_OBJS)
+\t@mkdir -p $(dir $@)
+\t{linker} $^ -o $@ $(LDFLAGS) $(TEST_LDFLAGS) $(LIBS) $(TEST_LIBS)
+
+test-%: $(TEST_BIN_DIR)/%
+\t$<
+")?,
+            Some(name) => write!(file, "TEST_BINS:=$(TEST_BIN_DIR)/{name}
+
+$(TEST_BIN_DIR)/{name}: $(TEST_OBJS) $(COMMON_OBJS)
+\t@mkdir -p $(dir $@)
+\t{linker} $^ -o $@ $(LDFLAGS) $(TEST_LDFLAGS) $(LIBS) $(TEST_LIBS)
+
+.PHONY: test-{name}
+test-{name}: $(TEST_BIN_DIR)/{name}
+\t$<
+")?
         }
 
-        file.write(b"
-clear: 
-\t-@rm -f $(BIN_DIR)/$(EXEC) 2> /dev/null
-\t-@find . -name '*.o' -exec rm {} \\; 2> /dev/null
-        ")?;
+        //runs every test executable even if one fails, and fails if any did
+        write!(file, "
+.PHONY: test
+test: $(TEST_BINS)
+\t@failed=0; for t in $^; do echo \"Running $$t\"; $$t || {{ echo \"FAILED: $$t\"; failed=$$((failed+1)); }}; done; \\
+\tif [ $$failed -ne 0 ]; then echo \"$$failed test executable(s) failed\"; exit 1; fi; echo \"All tests passed\"
+")?;
 
+        for ext in exts {
+            self.write_object_rule(file, &concat_str_post(dir, "/"), &concat_str_post(dir, "/"), ext)?;
+        }
         Ok(())
     }
+
+    /// Writes the pattern rule building objects from sources with the given extension
+    fn write_object_rule(&self, file: &mut impl Write, obj_prefix: &str, src_prefix: &str, ext: &str) -> Result<(), io::Error> {
+        let lang = self.language_of(ext);
+        write!(file, "
+$(OBJ_DIR)/{obj_prefix}%.o: {src_prefix}%.{ext}
+\t@mkdir -p $(dir $@)
+\t$({}) $(CPPFLAGS) $({}) $(DEPFLAGS) -c $< -o $@
+            ",
+            lang.compiler_var(),
+            lang.flags_var()
+        )
+    }
 }
 
-fn write_target_name(file: &mut File, name: &str) -> Result<(), std::io::Error>{
-    file.write(name.as_bytes())?;
-    file.write(b": ")?;
+/// Quotes a command line argument for the shell if needed, and escapes it for make
+fn shell_arg(arg: &str) -> String {
+    let arg = if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=+,:@%".contains(c)) {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    };
+    arg.replace('$', "$$")
+}
+
+/// Writes a rule making the Makefile depend on the config file, rerunning goombuild with the
+/// same arguments when the config is more recent. Like every path of the Makefile, those of the
+/// dependencies are relative to the directory goombuild was run in, that make is run from
+fn write_regeneration_rule(file: &mut impl Write, invocation: &Invocation, watched_dirs: &[String]) -> Result<(), io::Error> {
+    let escape = |path: &str| path.replace('$', "$$").replace(' ', "\\ ");
+    let mut deps = vec![escape(invocation.input_file)];
+    deps.extend(invocation.base_files.iter().map(|base| escape(base)));
+    deps.extend(watched_dirs.iter().map(|dir| escape(dir)));
+    let args: Vec<String> = invocation.args.iter().map(|arg| shell_arg(arg)).collect();
+
+    write!(file, "
+#--- Regenerates this Makefile when the config changes
+GOOMBUILD?=goombuild
+$(THIS_MAKEFILE): {}
+\t$(GOOMBUILD){}
+",
+        deps.join(" "),
+        string_if(!args.is_empty(), || concat_str(" ", args.join(" ")))
+    )
+}
+
+/// Explicit list of source files, one per line
+fn static_list(files: &[String]) -> String {
+    files.iter().map(|file| format!("\\\n\t{file} ")).collect::<String>().trim_end().to_owned()
+}
+
+/// find arguments matching files with any of the extensions
+fn find_names(exts: &[&str]) -> String {
+    let names: Vec<String> = exts.iter().map(|ext| format!("-name \"*.{ext}\"")).collect();
+    if names.len() > 1 {format!("\\( {} \\)", names.join(" -o "))} else {names.join("")}
+}
+
+/// Writes the target prefix of a target-specific variable, if the variable is scoped to a target
+fn write_target_name(file: &mut impl Write, name: Option<&str>) -> Result<(), std::io::Error>{
+    if let Some(name) = name {
+        file.write_all(name.as_bytes())?;
+        file.write_all(b": ")?;
+    }
     Ok(())
 }
 
-fn write_target_var_(file: &mut File, varname: &[u8], val: &str, target_name: &str) -> Result<(), io::Error> {
+fn write_target_var_(file: &mut impl Write, varname: &[u8], val: &str, target_name: Option<&str>) -> Result<(), io::Error> {
     write_target_name(file, target_name)?;
     write_var(file, varname, val)?;
 
     Ok(())
 }
 
-fn write_target_var(file: &mut File, varname: &[u8], val: Option<&str>, target_name: &str) -> Result<(), io::Error> {
+fn write_target_var(file: &mut impl Write, varname: &[u8], val: Option<&str>, target_name: Option<&str>) -> Result<(), io::Error> {
     if let Some(val) = val {
         write_target_var_(file, varname, val, target_name)?
     };
     Ok(())
 }
 
-fn write_target_var_with_expansion(file: &mut File, varname: &[u8], val: Option<&str>, target_name: &str) -> Result<(), io::Error> {
+fn write_target_var_with_expansion(file: &mut impl Write, varname: &[u8], val: Option<&str>, target_name: Option<&str>) -> Result<(), io::Error> {
     if let Some(val) = val {
-        let val = if val.contains("+") {
-            val.replace("+", (String::from("$(") + unsafe {str::from_utf8_unchecked(varname)} + ")").as_str())
-        } else {
-            val.to_owned()
-        };
+        let val = expand_plus(val, unsafe {str::from_utf8_unchecked(varname)});
         write_target_var_(file, varname, val.as_str(), target_name)?;
     };
 
     Ok(())
 }
 
-impl<'a> Target<'a> {
-    pub fn write(&self, file: &mut File) -> Result<(), std::io::Error>{
-        nl(file)?;
-        let mut dependency = "all";
-        if let Some(exec_name) = self.config.exec_name {
-            write!(file,"
-$(BIN_DIR)/{}: $(OBJS)
-\t$(CC) $^ -o $@ $(LDFLAGS) $(LIBS)
-            \n", exec_name)?;
-            dependency = exec_name;
+impl BuildConfig<'_> {
+    /// Writes the variables this config overrides, either for a target (target-specific variables)
+    /// or unscoped (inside a profile conditional)
+    fn write_overrides(&self, file: &mut impl Write, kind: Language, target_name: Option<&str>) -> Result<(), io::Error> {
+        for lang in [Language::C, Language::Cpp, Language::Asm] {
+            write_target_var(file, lang.compiler_var().as_bytes(), self.compiler_for(lang, kind), target_name)?;
         }
+        write_target_var(file, b"AR", self.archiver, target_name)?;
+        for (varname, val) in self.flag_vars(true) {
+            write_target_var_(file, varname.as_bytes(), val.trim_end(), target_name)?;
+        }
+        write_target_var_with_expansion(file, b"LDFLAGS", self.ldflags, target_name)?;
+        write_target_var_with_expansion(file, b"ASFLAGS", self.asflags, target_name)?;
 
-        write_target_var(file, b"CC", self.config.compiler, self.name)?;
-        write_target_var_with_expansion(file, b"CFLAGS", self.config.cflags, self.name)?;
-        write_target_var_with_expansion(file, b"LDFLAGS", self.config.ldflags, self.name)?;
-
-        if let Some(libs) = &self.config.libs {
-            write_target_name(file, self.name)?;
-            file.write(b"LIBS:=")?;
+        if let Some(libs) = &self.libs {
+            write_target_name(file, target_name)?;
+            file.write_all(b"LIBS:=")?;
 
             for lib in libs {
-                file.write(b"-l")?;
+                file.write_all(b"-l")?;
                 match lib {
-                    &"+" => {file.write(b"$(LIBS)")},
-                    _ => {file.write(lib.as_bytes())}
+                    &"+" => {file.write_all(b"$(LIBS)")},
+                    _ => {file.write_all(lib.as_bytes())}
                 }?;
-                file.write(b" ")?;
+                file.write_all(b" ")?;
             }
             nl(file)?;
         }
+        Ok(())
+    }
+}
+
+impl<'a> Target<'a> {
+    fn write(&self, file: &mut impl Write, kind: Language) -> Result<(), std::io::Error>{
+        nl(file)?;
+        let mut dependency = String::from("all");
+        if let Some(exec_name) = self.config.exec_name {
+            write!(file,"
+$(BIN_DIR)/{}: $(OBJS)
+\t$(LINKER) $^ -o $@ $(LDFLAGS) $(LIBS)
+            \n", exec_name)?;
+            dependency = concat_str("start $(BIN_DIR)/", exec_name);
+        }
+
+        self.config.write_overrides(file, kind, Some(self.name))?;
 
         write!(file, "
 {}: {dependency}
@@ -262,4 +707,133 @@ $(BIN_DIR)/{}: $(OBJS)
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Default directory of a profile, a subdirectory of the global one
+pub(super) fn profile_dir(base: &str, name: &str) -> String {
+    if base == "." {name.to_owned()} else {format!("{base}/{name}")}
+}
+
+impl<'a> Profile<'a> {
+    /// Writes the conditional block selecting this profile's directories and flags
+    fn write_conditional(&self, file: &mut impl Write, config: &Config) -> Result<(), io::Error> {
+        writeln(file, &format!("ifeq ($(PROFILE),{})", self.name))?;
+        write_var(file, b"OBJ_DIR", &self.obj_dir.map_or_else(|| profile_dir(config.obj_dir, self.name), str::to_owned))?;
+        write_var(file, b"BIN_DIR", &self.bin_dir.map_or_else(|| profile_dir(config.bin_dir, self.name), str::to_owned))?;
+        write_target_var(file, b"EXEC", self.config.exec_name, None)?;
+        self.config.write_overrides(file, config.kind, None)?;
+        writeln(file, "endif")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{render, render_to};
+    use super::expand_plus;
+
+    #[test]
+    fn leading_plus_appends() {
+        assert_eq!(expand_plus("+ -O3", "EXTRAFLAGS"), "$(EXTRAFLAGS) -O3");
+        assert_eq!(expand_plus("+-O3", "EXTRAFLAGS"), "$(EXTRAFLAGS) -O3");
+        assert_eq!(expand_plus("+", "EXTRAFLAGS"), "$(EXTRAFLAGS)");
+    }
+
+    #[test]
+    fn inner_plus_is_kept() {
+        assert_eq!(expand_plus("-std=c++17 -DVERSION=1+2", "EXTRAFLAGS"), "-std=c++17 -DVERSION=1+2");
+        assert_eq!(expand_plus("+ -lstdc++", "LDFLAGS"), "$(LDFLAGS) -lstdc++");
+    }
+
+    #[test]
+    fn default_extra_flags_are_written_as_is() {
+        let makefile = render("kind: cpp\ncompile_flags: -std=c++17 -DVERSION=1+2\n");
+        assert!(makefile.contains("\nEXTRAFLAGS:=-std=c++17 -DVERSION=1+2\n"), "{makefile}");
+    }
+
+    #[test]
+    fn profile_extra_flags_append() {
+        let makefile = render("kind: cpp\ncompile_flags: -std=c++17\nprofiles:\n  - name: debug\n    compile_flags: + -fsanitize=address\n");
+        assert!(makefile.contains("\nEXTRAFLAGS:=$(EXTRAFLAGS) -fsanitize=address\n"), "{makefile}");
+    }
+
+    #[test]
+    fn profile_is_only_set_from_the_command_line() {
+        let makefile = render("kind: c\nprofiles:\n  - name: debug\n  - name: release\n");
+        assert!(makefile.contains("\nPROFILE:=debug\n"), "{makefile}");
+        assert!(makefile.contains("ifneq ($(filter-out clean clean-profile distclean clear,$(or $(MAKECMDGOALS),all)),)"), "{makefile}");
+    }
+
+    #[test]
+    fn regeneration_paths_are_relative_to_the_run_directory() {
+        let makefile = render_to("kind: c\nsource_listing: static\n", "build/Makefile");
+        assert!(makefile.contains("\n$(THIS_MAKEFILE): gbuild.yaml src"), "{makefile}");
+        assert!(!makefile.contains("cd "), "{makefile}");
+        assert!(!makefile.contains("../"), "{makefile}");
+    }
+
+    #[test]
+    fn cpp_tests_are_linked_with_the_cpp_compiler() {
+        let makefile = render("kind: c\ntests:\n  framework: gtest\n");
+        assert!(makefile.contains("\t@mkdir -p $(dir $@)\n\t$(CXX) $^ -o $@ $(LDFLAGS) $(TEST_LDFLAGS)"), "{makefile}");
+        assert!(makefile.contains("$(OBJ_DIR)/tests/%.o: tests/%.cpp"), "{makefile}");
+        let makefile = render("kind: c\ntests:\n  ext: [c, cc]\n");
+        assert!(makefile.contains("\t$(CXX) $^ -o $@"), "{makefile}");
+        let makefile = render("kind: c\ntests: tests\n");
+        assert!(makefile.contains("\t$(LINKER) $^ -o $@ $(LDFLAGS) $(TEST_LDFLAGS)"), "{makefile}");
+    }
+
+    #[test]
+    fn library_outputs_are_linked_from_the_objects() {
+        let makefile = render("kind: c\n");
+        assert!(makefile.contains("\n$(BIN_DIR)/$(EXEC): $(OBJS)\n\t$(LINKER) $^ -o $@"), "{makefile}");
+        let makefile = render("kind: c\ntype: static_lib\nlib_name: list\n");
+        assert!(makefile.contains("\n$(STATIC_LIB): $(OBJS)\n\t$(AR) rcs $@ $^\n"), "{makefile}");
+        assert!(!makefile.contains("$(EXEC)"), "{makefile}");
+        let makefile = render("kind: c\ntype: shared_lib\nlib_name: list\n");
+        assert!(makefile.contains("\n$(SHARED_LIB): $(OBJS)\n\t$(LINKER) -shared $^ -o $@"), "{makefile}");
+        assert!(!makefile.contains("SONAME"), "{makefile}");
+    }
+
+    #[test]
+    fn versioned_shared_libraries_are_symlinked() {
+        let makefile = render("kind: c\ntype: both\nlib_name: list\nversion: 1.2.3\n");
+        assert!(makefile.contains("\nSONAME:=lib$(LIB_NAME).so.1\n"), "{makefile}");
+        assert!(makefile.contains("\n$(STATIC_LIB): $(OBJS)\n"), "{makefile}");
+        assert!(makefile.contains("\n$(SHARED_LIB).$(VERSION): $(OBJS)\n\t$(LINKER) -shared -Wl,-soname,$(SONAME) $^ -o $@"), "{makefile}");
+        assert!(makefile.contains("\n$(SHARED_LIB): $(SHARED_LIB).$(VERSION)\n\tln -sf $(notdir $<) $(BIN_DIR)/$(SONAME)\n\tln -sf $(notdir $<) $@\n"), "{makefile}");
+        //with a major-only version, the real file is named after the soname
+        let makefile = render("kind: c\ntype: shared_lib\nlib_name: list\nversion: 2\n");
+        assert!(makefile.contains("\n$(SHARED_LIB): $(SHARED_LIB).$(VERSION)\n\tln -sf $(notdir $<) $@\n"), "{makefile}");
+    }
+
+    #[test]
+    fn executables_share_the_other_objects() {
+        let makefile = render("kind: c\nsources: src\ntype: both\nlib_name: net\nexecutables:\n  - name: server\n    main: src/server.c\n  - name: client\n    main: [src/client.c, src/cli/args.c]\n");
+        assert!(makefile.contains("\nMAIN_OBJS=$(OBJ_DIR)/server.o $(OBJ_DIR)/client.o $(OBJ_DIR)/cli/args.o\n"), "{makefile}");
+        assert!(makefile.contains("\nCOMMON_OBJS=$(filter-out $(MAIN_OBJS),$(OBJS))\n"), "{makefile}");
+        assert!(makefile.contains("\n$(BIN_DIR)/server: $(COMMON_OBJS) $(OBJ_DIR)/server.o\n"), "{makefile}");
+        assert!(makefile.contains("\n$(BIN_DIR)/client: $(COMMON_OBJS) $(OBJ_DIR)/client.o $(OBJ_DIR)/cli/args.o\n"), "{makefile}");
+        assert!(makefile.contains("\n$(STATIC_LIB): $(COMMON_OBJS)\n"), "{makefile}");
+        assert!(makefile.contains("\n$(SHARED_LIB): $(COMMON_OBJS)\n"), "{makefile}");
+        assert!(!makefile.contains("$(EXEC)"), "{makefile}");
+    }
+
+    #[test]
+    fn clean_removes_the_outputs_and_objects() {
+        let makefile = render("kind: c\ntype: shared_lib\nlib_name: list\nversion: 1.2.3\n");
+        assert!(makefile.contains("\nclean:\n\t-rm -f $(OUTPUTS) $(SHARED_LIB).$(VERSION) $(BIN_DIR)/$(SONAME)\n\tfind $(OBJ_DIR) "), "{makefile}");
+        assert!(makefile.contains("\ndistclean: clean\n\t-rm -f $(THIS_MAKEFILE)\n"), "{makefile}");
+        assert!(!makefile.contains("clean-profile"), "{makefile}");
+        //with the objects in the project root, only ours are removed
+        let makefile = render("kind: c\nobj_dir: .\n");
+        assert!(makefile.contains("\n\t-rm -f $(OBJS) $(OBJS:.o=.d)\n"), "{makefile}");
+    }
+
+    #[test]
+    fn clean_goes_through_every_profile() {
+        let makefile = render("kind: c\ntests: tests\nprofiles:\n  - name: debug\n  - name: release\n");
+        assert!(makefile.contains("\nclean:\n\t@$(MAKE) --no-print-directory clean-profile PROFILE=debug\n\t@$(MAKE) --no-print-directory clean-profile PROFILE=release\n"), "{makefile}");
+        assert!(makefile.contains("\n.PHONY: clean-profile\nclean-profile:\n\t-rm -f $(OUTPUTS) $(TEST_BINS)\n"), "{makefile}");
+        assert!(makefile.contains("\t@rmdir -p $(BIN_DIR) 2> /dev/null || true\n"), "{makefile}");
+    }
+}
@@ -0,0 +1,212 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::{json::Json, read_yaml::{ContextfulError, YamlResult}, source_map::Snippet};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    Note,
+    Warning,
+    Error
+}
+
+impl Severity {
+    fn name(&self) -> &'static str {
+        match self {
+            Severity::Note => "note",
+            Severity::Warning => "warning",
+            Severity::Error => "error"
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Note => "Note",
+            Severity::Warning => "Warning",
+            Severity::Error => "Error"
+        })
+    }
+}
+
+/// How messages about the run are printed
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum MessageFormat {
+    #[default]
+    Human,
+    Json
+}
+
+impl FromStr for MessageFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => Err("message format should be either human or json")
+        }
+    }
+}
+
+/// A message about the run, printed on stderr as text or as a JSON object
+pub struct Message<'a> {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub context: String,
+    pub snippet: Option<Snippet<'a>>
+}
+
+impl <'a> Message<'a> {
+    pub fn new(severity: Severity, code: &'static str, message: impl ToString) -> Self {
+        Message{severity, code, message: message.to_string(), context: String::new(), snippet: None}
+    }
+
+    pub fn from_error(severity: Severity, error: &ContextfulError) -> Self {
+        Message{severity, code: error.code(), message: error.message(), context: error.context().to_owned(), snippet: None}
+    }
+
+    pub fn with_snippet(mut self, snippet: Option<Snippet<'a>>) -> Self {
+        self.snippet = snippet;
+        self
+    }
+
+    /// Prints the message, starting with `prefix` when printed as text
+    pub fn print(&self, format: MessageFormat, prefix: &str) {
+        match format {
+            MessageFormat::Human => {
+                match self.context.as_str() {
+                    "" => eprintln!("{prefix}{}", self.message),
+                    context => eprintln!("{prefix}{context} {}", self.message)
+                }
+                if let Some(snippet) = &self.snippet {
+                    eprintln!("{snippet}");
+                }
+            },
+            MessageFormat::Json => eprintln!("{}", self.to_json())
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        let position = self.snippet.as_ref().map(|snippet| Json::Object(vec![
+            ("file", snippet.filename().into()),
+            ("line", Json::Number(snippet.line() as i64)),
+            ("column", Json::Number(snippet.column() as i64))
+        ]));
+        Json::Object(vec![
+            ("severity", self.severity.name().into()),
+            ("code", self.code.into()),
+            ("message", self.message.as_str().into()),
+            ("context", self.context.as_str().into()),
+            ("position", position.into())
+        ])
+    }
+}
+
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub error: ContextfulError
+}
+
+/// Problems found while reading the config, collected so that they can all be reported at once
+pub struct Diagnostics {
+    list: Vec<Diagnostic>,
+    //what is being read, outermost first
+    contexts: Vec<String>,
+    //warnings are reported as errors
+    strict: bool
+}
+
+impl Diagnostics {
+    pub fn new(strict: bool) -> Self {
+        Diagnostics{list: Vec::new(), contexts: Vec::new(), strict}
+    }
+
+    fn push(&mut self, severity: Severity, mut error: ContextfulError) {
+        for context in self.contexts.iter().rev() {
+            error = error.add_context(context);
+        }
+        self.list.push(Diagnostic{severity, error});
+    }
+
+    pub fn warn(&mut self, error: ContextfulError) {
+        self.push(if self.strict {Severity::Error} else {Severity::Warning}, error);
+    }
+
+    pub fn error(&mut self, error: ContextfulError) {
+        self.push(Severity::Error, error);
+    }
+
+    /// Records the error of a result if any, so that reading can go on without the value
+    pub fn ok<T>(&mut self, result: Result<T, ContextfulError>) -> Option<T> {
+        result.map_err(|err| self.error(err)).ok()
+    }
+
+    /// Like `ok`, for optional values
+    pub fn value<T>(&mut self, result: YamlResult<T>) -> Option<T> {
+        self.ok(result).flatten()
+    }
+
+    /// Runs `read` with the context added to every diagnostic it records
+    pub fn in_context<R>(&mut self, context: impl ToString, read: impl FnOnce(&mut Self) -> R) -> R {
+        self.contexts.push(context.to_string());
+        let result = read(self);
+        self.contexts.pop();
+        result
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.list.iter().any(|diag| diag.severity == Severity::Error)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.list.iter()
+    }
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(prev[j + 1] + 1).min(current[j] + 1);
+        }
+        prev = current;
+    }
+    prev[b.len()]
+}
+
+/// The candidate closest to `name`, if it's close enough to be a likely typo
+pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = name.chars().count().max(3) / 3;
+    candidates.into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{closest, edit_distance};
+
+    #[test]
+    fn distances() {
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("src_dir", "src_dir"), 0);
+        assert_eq!(edit_distance("src_dri", "src_dir"), 2);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn closest_key() {
+        let keys = ["src_dir", "src_ext", "include", "obj_dir"];
+        assert_eq!(closest("src_dri", keys), Some("src_dir"));
+        assert_eq!(closest("includes", keys), Some("include"));
+        assert_eq!(closest("warnings", keys), None);
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt::Write;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert
+}
+
+/// Line-by-line edit script turning `old` into `new`, from their longest common subsequence
+fn edit_script(old: &[&str], new: &[&str]) -> Vec<Op> {
+    //lcs[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {lcs[i + 1][j + 1] + 1} else {lcs[i + 1][j].max(lcs[i][j + 1])};
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut ops = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push(Op::Equal);
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(Op::Delete);
+            i += 1;
+        } else {
+            ops.push(Op::Insert);
+            j += 1;
+        }
+    }
+    ops
+}
+
+/// Unified diff between two texts, with `context` lines around each change. Empty if they are the same
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str, context: usize) -> String {
+    //lines keep their ending, so a missing final newline or a CR/LF is a difference too
+    let old: Vec<&str> = old.split_inclusive('\n').collect();
+    let new: Vec<&str> = new.split_inclusive('\n').collect();
+    let ops = edit_script(&old, &new);
+
+    //line of each text at every step of the script
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut i, mut j) = (0, 0);
+    for op in &ops {
+        positions.push((i, j));
+        match op {
+            Op::Equal => {i += 1; j += 1},
+            Op::Delete => i += 1,
+            Op::Insert => j += 1
+        }
+    }
+    positions.push((i, j));
+
+    let mut diff = String::new();
+    let changes: Vec<usize> = ops.iter().enumerate().filter(|(_, op)| **op != Op::Equal).map(|(k, _)| k).collect();
+    let mut k = 0;
+    while k < changes.len() {
+        //a hunk goes on as long as changes are close enough for their contexts to touch
+        let start = changes[k].saturating_sub(context);
+        let mut end = changes[k] + 1;
+        while k + 1 < changes.len() && changes[k + 1] <= end + 2 * context {
+            k += 1;
+            end = changes[k] + 1;
+        }
+        let end = (end + context).min(ops.len());
+        k += 1;
+
+        if diff.is_empty() {
+            let _ = writeln!(diff, "--- {old_name}\n+++ {new_name}");
+        }
+        let ((old_start, new_start), (old_end, new_end)) = (positions[start], positions[end]);
+        let range = |start: usize, len: usize| if len == 0 {format!("{start},0")} else {format!("{},{len}", start + 1)};
+        let _ = writeln!(diff, "@@ -{} +{} @@", range(old_start, old_end - old_start), range(new_start, new_end - new_start));
+        for (op, (i, j)) in ops[start..end].iter().zip(&positions[start..end]) {
+            let (prefix, line) = match op {
+                Op::Equal => (' ', old[*i]),
+                Op::Delete => ('-', old[*i]),
+                Op::Insert => ('+', new[*j])
+            };
+            diff.push(prefix);
+            diff.push_str(line);
+            if !line.ends_with('\n') {
+                diff.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unified_diff;
+
+    fn lines(range: std::ops::RangeInclusive<u32>) -> String {
+        range.map(|n| format!("{n}\n")).collect()
+    }
+
+    #[test]
+    fn same_texts_have_no_diff() {
+        assert_eq!(unified_diff("a\nb\n", "a\nb\n", "old", "new", 3), "");
+    }
+
+    #[test]
+    fn changed_line_with_context() {
+        assert_eq!(unified_diff("a\nb\nc\n", "a\nB\nc\n", "old", "new", 1), "--- old\n+++ new\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n");
+    }
+
+    #[test]
+    fn distant_changes_get_their_own_hunks() {
+        let new = lines(1..=10).replace("1\n2", "x\n2").replace("10\n", "y\n");
+        assert_eq!(unified_diff(&lines(1..=10), &new, "old", "new", 1),
+            "--- old\n+++ new\n@@ -1,2 +1,2 @@\n-1\n+x\n 2\n@@ -9,2 +9,2 @@\n 9\n-10\n+y\n");
+    }
+
+    #[test]
+    fn close_changes_share_a_hunk() {
+        let new = lines(1..=5).replace("1\n", "x\n").replace("5\n", "y\n");
+        assert_eq!(unified_diff(&lines(1..=5), &new, "old", "new", 2),
+            "--- old\n+++ new\n@@ -1,5 +1,5 @@\n-1\n+x\n 2\n 3\n 4\n-5\n+y\n");
+    }
+
+    #[test]
+    fn new_file() {
+        assert_eq!(unified_diff("", "a\nb\n", "old", "new", 3), "--- old\n+++ new\n@@ -0,0 +1,2 @@\n+a\n+b\n");
+    }
+
+    #[test]
+    fn missing_final_newline_is_marked() {
+        assert_eq!(unified_diff("a\nb\n", "a\nb", "old", "new", 1), "--- old\n+++ new\n@@ -1,2 +1,2 @@\n a\n-b\n+b\n\\ No newline at end of file\n");
+    }
+
+    #[test]
+    fn line_endings_differ() {
+        assert_eq!(unified_diff("a\r\nb\n", "a\nb\n", "old", "new", 0), "--- old\n+++ new\n@@ -1,1 +1,1 @@\n-a\r\n+a\n");
+    }
+}
@@ -0,0 +1,215 @@
+use std::{fs, mem, path::{Path, PathBuf}};
+
+use yaml_rust2::{yaml::Hash, Yaml};
+
+use crate::diagnostics::Diagnostics;
+use crate::read_yaml::{get_doc, handle_wrong_type, load_yaml, read_source, yaml_type_name, ContextfulError, ReadError};
+
+/// A config file extended by the main one, kept to point to its values and to regenerate the Makefile when it changes
+pub struct BaseFile {
+    pub path: String,
+    pub source: String
+}
+
+/// A base config file that couldn't be loaded
+pub struct BaseError {
+    pub path: String,
+    pub extended_by: String,
+    //for syntax errors to be shown in their source
+    pub source: Option<String>,
+    pub error: ReadError
+}
+
+/// Removes the `extends` key of a config, returning the files it names
+fn take_extends(data: &mut Yaml, filename: &str, diagnostics: &mut Diagnostics) -> Vec<String> {
+    let Yaml::Hash(hash) = data else {return Vec::new()};
+    match hash.remove(&Yaml::from_str("extends")) {
+        None | Some(Yaml::Null) => Vec::new(),
+        Some(Yaml::String(name)) => vec![name],
+        Some(Yaml::Array(names)) => names.into_iter().filter_map(|name| match name {
+            Yaml::String(name) => Some(name),
+            val => {
+                diagnostics.error(handle_wrong_type(&val, "string").add_context(format!("In {filename} extends")).with_code("extends"));
+                None
+            }
+        }).collect(),
+        Some(val) => {
+            diagnostics.error(handle_wrong_type(&val, "string or array thereof").add_context(format!("In {filename} extends")).with_code("extends"));
+            Vec::new()
+        }
+    }
+}
+
+/// Appends the values of a `+key` to the list `key` of the base table. A single value there
+/// (e.g. `include: inc`, that most list keys accept) is the first item of the list
+fn append(base: &mut Hash, name: &str, value: Yaml, file: &str, diagnostics: &mut Diagnostics) {
+    let items = match value {
+        Yaml::Array(items) => items,
+        value => vec![value]
+    };
+    match base.get_mut(&Yaml::from_str(name)) {
+        None | Some(Yaml::Null) => {base.insert(Yaml::from_str(name), Yaml::Array(items));},
+        Some(Yaml::Array(list)) => list.extend(items),
+        Some(val @ Yaml::Hash(_)) => diagnostics.error(ContextfulError::from(format!("+{name} appends to a list, but {name} is a {} in the configs it extends", yaml_type_name(val))).add_context(format!("In {file}")).with_code("extends")),
+        Some(val) => {
+            let first = mem::replace(val, Yaml::Null);
+            *val = Yaml::Array(std::iter::once(first).chain(items).collect());
+        }
+    }
+}
+
+/// Merges the config `over`, read from `file`, into `base` : tables are merged key by key, other values
+/// replace those of the base, and the values of a `+key` are appended to the list `key`
+fn merge(base: &mut Yaml, over: Yaml, file: &str, diagnostics: &mut Diagnostics) {
+    match (base, over) {
+        (Yaml::Hash(base), Yaml::Hash(over)) => for (key, value) in over {
+            if let Some(name) = key.as_str().and_then(|key| key.strip_prefix('+')) {
+                append(base, name, value, file, diagnostics);
+                continue
+            }
+            match base.get_mut(&key) {
+                Some(base_value) => merge(base_value, value, file, diagnostics),
+                None => {
+                    let mut new_value = Yaml::Null;
+                    merge(&mut new_value, value, file, diagnostics);
+                    base.insert(key, new_value);
+                }
+            }
+        },
+        //a table replacing something else still has its +keys expanded
+        (base, over @ Yaml::Hash(_)) => {
+            *base = Yaml::Hash(Hash::new());
+            merge(base, over, file, diagnostics);
+        },
+        (base, over) => *base = over
+    }
+}
+
+/// Loads and merges the files a config extends, then merges the config over them
+fn load(data: &mut Yaml, filename: &str, chain: &mut Vec<PathBuf>, files: &mut Vec<BaseFile>, diagnostics: &mut Diagnostics) -> Result<Yaml, BaseError> {
+    //paths are relative to the file they are written in, or to the current directory for stdin
+    let dir = Path::new(filename).parent().unwrap_or(Path::new(""));
+    let mut merged = Yaml::Hash(Hash::new());
+    for name in take_extends(data, filename, diagnostics) {
+        let path = dir.join(&name).display().to_string();
+        let base_error = |source: Option<String>, error: ReadError| BaseError{path: path.clone(), extended_by: filename.to_owned(), source, error};
+        let canonical = fs::canonicalize(&path).map_err(|err| base_error(None, err.into()))?;
+        if chain.contains(&canonical) {
+            diagnostics.error(ContextfulError::from(format!("{filename} extends {name}, which already extends it")).with_code("extends"));
+            continue
+        }
+        let source = read_source(&path).map_err(|err| base_error(None, err))?;
+        let mut docs = match load_yaml(&source) {
+            Ok(docs) => docs,
+            Err(err) => return Err(base_error(Some(source), err))
+        };
+        let doc = get_doc(&mut docs).map_err(|err| base_error(None, err))?;
+
+        chain.push(canonical);
+        let base = load(doc, &path, chain, files, diagnostics)?;
+        chain.pop();
+        merge(&mut merged, base, &path, diagnostics);
+        files.push(BaseFile{path, source});
+    }
+    merge(&mut merged, mem::replace(data, Yaml::Null), filename, diagnostics);
+    Ok(merged)
+}
+
+/// Replaces the config with its merge over the files it `extends`, recursively.
+/// Returns the base files, in the order they were merged
+pub fn extend(data: &mut Yaml, filename: &str, diagnostics: &mut Diagnostics) -> Result<Vec<BaseFile>, BaseError> {
+    let mut chain = Vec::new();
+    if let Ok(canonical) = fs::canonicalize(filename) {
+        chain.push(canonical);
+    }
+    let mut files = Vec::new();
+    *data = load(data, filename, &mut chain, &mut files, diagnostics)?;
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use yaml_rust2::Yaml;
+
+    use crate::diagnostics::Diagnostics;
+    use crate::test_util::{temp_dir, yaml};
+    use super::{extend, merge};
+
+    /// `over` merged into `base`, with the messages of the errors
+    fn merged(base: &str, over: &str) -> (Yaml, Vec<String>) {
+        let mut base = yaml(base);
+        let mut diagnostics = Diagnostics::new(false);
+        merge(&mut base, yaml(over), "child.yaml", &mut diagnostics);
+        (base, diagnostics.iter().map(|diag| diag.error.to_string()).collect())
+    }
+
+    #[test]
+    fn tables_merge_and_values_replace() {
+        let (merged, errors) = merged("kind: c\nlibs: [m]\ntests:\n  dir: tests\n  mode: unit\n", "libs: [pthread]\ntests:\n  dir: t\n");
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(merged, yaml("kind: c\nlibs: [pthread]\ntests:\n  dir: t\n  mode: unit\n"));
+    }
+
+    #[test]
+    fn plus_keys_append() {
+        let (merged, errors) = merged("libs: [m]\n", "+libs: [pthread]\n+warnings: all\n");
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(merged, yaml("libs: [m, pthread]\nwarnings: [all]\n"));
+    }
+
+    #[test]
+    fn plus_keys_append_to_single_values() {
+        let (merged, errors) = merged("include: inc\ndefines:\n", "+include: [extra]\n+defines: [X]\n");
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(merged, yaml("include: [inc, extra]\ndefines: [X]\n"));
+    }
+
+    #[test]
+    fn plus_keys_of_new_tables_append() {
+        let (merged, errors) = merged("kind: c\n", "tests:\n  dir: tests\n  +libs: [x]\n");
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(merged, yaml("kind: c\ntests:\n  dir: tests\n  libs: [x]\n"));
+    }
+
+    #[test]
+    fn plus_keys_of_tables_replacing_values_append() {
+        let (merged, errors) = merged("tests: tests\n", "tests:\n  +libs: [x]\n");
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(merged, yaml("tests:\n  libs: [x]\n"));
+    }
+
+    #[test]
+    fn plus_keys_cant_append_to_tables() {
+        let (_, errors) = merged("tests:\n  dir: t\n", "+tests: [x]\n");
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(errors[0].contains("tests is a table"), "{errors:?}");
+    }
+
+    #[test]
+    fn files_extend_each_other() {
+        let dir = temp_dir("extends");
+        fs::create_dir_all(dir.join("base")).unwrap();
+        fs::write(dir.join("base/common.yaml"), "kind: c\ninclude: inc\n").unwrap();
+        fs::write(dir.join("base/loop.yaml"), "extends: ../gbuild.yaml\n").unwrap();
+        let filename = dir.join("gbuild.yaml").display().to_string();
+
+        let mut data = yaml("extends: base/common.yaml\n+include: [extra]\n");
+        let mut diagnostics = Diagnostics::new(false);
+        let files = extend(&mut data, &filename, &mut diagnostics).ok().unwrap();
+        assert_eq!(data, yaml("kind: c\ninclude: [inc, extra]\n"));
+        assert_eq!(files.len(), 1);
+        assert!(files[0].path.ends_with("common.yaml"));
+        assert_eq!(diagnostics.iter().count(), 0);
+
+        fs::write(&filename, "extends: base/loop.yaml\n").unwrap();
+        let mut data = yaml("extends: base/loop.yaml\n");
+        let result = extend(&mut data, &filename, &mut diagnostics);
+        let _ = fs::remove_dir_all(&dir);
+        assert!(result.is_ok());
+        let errors: Vec<String> = diagnostics.iter().map(|diag| diag.error.to_string()).collect();
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(errors[0].contains("which already extends it"), "{errors:?}");
+    }
+}
@@ -0,0 +1,330 @@
+use std::{collections::HashMap, fmt::Write, fs, io, path::Path};
+
+use crate::config::defines_main;
+
+/// Directories holding build outputs or tooling rather than project files
+const SKIPPED_DIRS: [&str; 6] = ["obj", "build", "bin", "target", "out", "node_modules"];
+/// Usual names of source directories, in order of preference
+const SOURCE_DIRS: [&str; 5] = ["src", "source", "sources", "lib", "libs"];
+const INCLUDE_DIRS: [&str; 3] = ["include", "inc", "headers"];
+/// Usual names of directories holding prebuilt libraries
+const LIB_DIRS: [&str; 2] = ["lib", "libs"];
+
+/// Project language of a source extension, as written in `kind`
+fn language(ext: &str) -> Option<&'static str> {
+    match ext {
+        "c" => Some("c"),
+        "cpp" | "cc" | "cxx" | "c++" | "cp" | "CPP" | "C" => Some("cpp"),
+        "s" | "S" | "sx" | "asm" => Some("asm"),
+        _ => None
+    }
+}
+
+fn extension(path: &str) -> Option<&str> {
+    Path::new(path).extension().and_then(|ext| ext.to_str())
+}
+
+/// Every file under `dir`, skipping hidden and build directories
+fn walk(dir: &Path, display: &str, files: &mut Vec<String>) -> Result<(), io::Error> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {continue};
+        if name.starts_with('.') {
+            continue
+        }
+        let path = if display.is_empty() {name.to_owned()} else {format!("{display}/{name}")};
+        if entry.file_type()?.is_dir() {
+            if !SKIPPED_DIRS.contains(&name) {
+                walk(&entry.path(), &path, files)?;
+            }
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Writes a string as a YAML scalar, quoted unless it is plain enough
+fn yaml_str(str: &str) -> String {
+    let plain = !str.is_empty()
+        && str.chars().all(|c| c.is_ascii_alphanumeric() || "_./+-".contains(c))
+        && !str.starts_with('-');
+    if plain {
+        str.to_owned()
+    } else {
+        format!("\"{}\"", str.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+fn yaml_list(values: &[String]) -> String {
+    format!("[{}]", values.iter().map(|value| yaml_str(value)).collect::<Vec<_>>().join(", "))
+}
+
+/// What `init` found out about the project in a directory
+pub struct Project {
+    name: Option<String>,
+    kinds: Vec<&'static str>,
+    exts: Vec<String>,
+    source_dirs: Vec<String>,
+    include_dirs: Vec<String>,
+    lib_dir: Option<String>,
+    libs: Vec<String>,
+    mains: Vec<String>,
+    source_count: usize
+}
+
+impl Project {
+    /// Looks through the files under `dir` for sources, headers, libraries and entry points
+    pub fn scan(dir: &Path) -> Result<Project, io::Error> {
+        let mut files = Vec::new();
+        walk(dir, "", &mut files)?;
+        files.sort();
+        let top_dir = |file: &str| file.split_once('/').map(|(top, _)| top.to_owned());
+        let is_source = |file: &str| extension(file).and_then(language).is_some();
+
+        let mut source_dirs: Vec<String> = SOURCE_DIRS.iter()
+            .filter(|name| files.iter().any(|file| is_source(file) && top_dir(file).as_deref() == Some(**name)))
+            .map(|name| name.to_string())
+            .collect();
+        if source_dirs.is_empty() && files.iter().any(|file| is_source(file)) {
+            source_dirs.push(".".to_owned());
+        }
+        let in_sources = |file: &str| source_dirs.iter().any(|dir| dir == "." || top_dir(file).as_deref() == Some(dir));
+        let sources: Vec<&String> = files.iter().filter(|file| is_source(file) && in_sources(file)).collect();
+
+        let mut languages = HashMap::<&str, usize>::new();
+        let mut ext_counts = HashMap::<&str, usize>::new();
+        for source in &sources {
+            let Some(ext) = extension(source) else {continue};
+            *ext_counts.entry(ext).or_default() += 1;
+            if let Some(lang) = language(ext).filter(|lang| *lang != "asm") {
+                *languages.entry(lang).or_default() += 1;
+            }
+        }
+        //the most used language first, as it is the main one of mixed projects
+        let mut kinds: Vec<&'static str> = ["cpp", "c"].into_iter().filter(|lang| languages.contains_key(lang)).collect();
+        kinds.sort_by_key(|lang| std::cmp::Reverse(languages[lang]));
+        if kinds.is_empty() {
+            kinds.push(if ext_counts.is_empty() {"cpp"} else {"c"});
+        }
+        let mut exts: Vec<&str> = ext_counts.keys().copied().collect();
+        exts.sort_by_key(|ext| (std::cmp::Reverse(ext_counts[ext]), ext.to_string()));
+
+        let include_dirs = INCLUDE_DIRS.iter()
+            .filter(|name| dir.join(name).is_dir())
+            .map(|name| name.to_string())
+            .collect();
+
+        let mut lib_dir = None;
+        let mut libs = Vec::new();
+        for name in LIB_DIRS {
+            let prefix = format!("{name}/");
+            for file in files.iter().filter_map(|file| file.strip_prefix(&prefix)) {
+                //libfoo.a and libfoo.so are linked with -lfoo
+                let lib = file.strip_prefix("lib").and_then(|lib| lib.strip_suffix(".a").or(lib.strip_suffix(".so")));
+                if let Some(lib) = lib.filter(|lib| !lib.contains('/')) {
+                    lib_dir = Some(name.to_owned());
+                    if !libs.iter().any(|known| known == lib) {
+                        libs.push(lib.to_owned());
+                    }
+                }
+            }
+            if lib_dir.is_some() {
+                break
+            }
+        }
+
+        let mains = sources.iter()
+            .filter(|file| fs::read_to_string(dir.join(file)).is_ok_and(|content| content.lines().any(defines_main)))
+            .map(|file| file.to_string())
+            .collect();
+
+        let name = fs::canonicalize(dir)?.file_name()
+            .and_then(|name| name.to_str())
+            .filter(|name| name.chars().all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c)))
+            .map(str::to_owned);
+
+        Ok(Project {
+            name,
+            kinds,
+            exts: exts.into_iter().map(str::to_owned).collect(),
+            source_dirs,
+            include_dirs,
+            lib_dir,
+            libs,
+            mains,
+            source_count: sources.len()
+        })
+    }
+
+    /// One line summary of what was found, for the user to check
+    pub fn summary(&self) -> String {
+        let dirs = if self.source_dirs.is_empty() {String::new()} else {format!(" in {}", self.source_dirs.join(", "))};
+        format!("{} project : {} source files{dirs}, {} defining main", self.kinds.join("/"), self.source_count, self.mains.len())
+    }
+
+    /// The commented config file describing the project
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        //writing into a String can't fail
+        let mut line = |str: &str| {let _ = writeln!(out, "{str}");};
+
+        line("# Build config read by goombuild, written by `goombuild init`");
+        line("# Run `goombuild` to generate the Makefile, then `make`");
+        line("");
+        line("# Language of the project : c, cpp, or a list of them for mixed projects (main one first)");
+        if self.kinds.len() == 1 {
+            line(&format!("kind: {}", self.kinds[0]));
+        } else {
+            line(&format!("kind: [{}]", self.kinds.join(", ")));
+        }
+        if self.mains.is_empty() && self.source_count > 0 {
+            line("# No main function was found, so this builds a library (static_lib, shared_lib or both)");
+            line("type: static_lib");
+            if let Some(name) = &self.name {
+                line(&format!("lib_name: {}", yaml_str(name)));
+            }
+        } else if let (Some(name), 0 | 1) = (&self.name, self.mains.len()) {
+            line("# Name of the executable");
+            line(&format!("exec: {}", yaml_str(name)));
+        }
+        line("");
+
+        line("# Directories searched for sources, recursively");
+        match self.source_dirs.as_slice() {
+            [] => line("# No sources were found yet, src is the default\nsrc_dir: src"),
+            [dir] => line(&format!("src_dir: {}", yaml_str(dir))),
+            dirs => line(&format!("sources: {}", yaml_list(dirs)))
+        }
+        //the kind alone compiles files named after it (.c, .cpp)
+        let mut default_exts = self.kinds.clone();
+        default_exts.sort();
+        let mut exts: Vec<&str> = self.exts.iter().map(String::as_str).collect();
+        exts.sort();
+        if !exts.is_empty() && exts != default_exts {
+            line("# Extensions of the source files");
+            line(&format!("src_ext: {}", yaml_list(&self.exts)));
+        }
+        if !self.include_dirs.is_empty() {
+            line("# Directories searched for headers (-I)");
+            line(&format!("include: {}", yaml_list(&self.include_dirs)));
+        }
+        line("obj_dir: obj");
+        line("bin_dir: .");
+        line("");
+
+        if let Some(lib_dir) = &self.lib_dir {
+            line(&format!("# Prebuilt libraries found in {lib_dir}"));
+            line(&format!("link_flags: {}", yaml_str(&format!("-L{lib_dir}"))));
+            line(&format!("libs: {}", yaml_list(&self.libs)));
+        } else {
+            line("# Libraries to link with (-l)");
+            line("libs: []");
+        }
+        line("warnings: [all, extra]");
+        line(if self.kinds[0] == "cpp" {"# std: c++20"} else {"# std: c11"});
+
+        match self.mains.as_slice() {
+            [] => (),
+            [main] => {
+                line("");
+                line("# Source defining the main function, left out of libraries and tests");
+                line(&format!("main: {}", yaml_str(main)));
+            },
+            mains => {
+                line("");
+                line("# One executable per source defining a main function");
+                line("executables:");
+                for main in mains {
+                    let name = Path::new(main).file_stem().and_then(|stem| stem.to_str()).unwrap_or(main);
+                    line(&format!("  - name: {}", yaml_str(name)));
+                    line(&format!("    main: {}", yaml_str(main)));
+                }
+            }
+        }
+        line("");
+        line("# Build variants, selected with `make PROFILE=<name>`");
+        line("profiles:");
+        line("  - name: debug");
+        line("    debug_info: true");
+        line("    optimize: 0");
+        line("  - name: release");
+        line("    optimize: 2");
+        line("    defines: [NDEBUG]");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::config::Config;
+    use crate::diagnostics::Diagnostics;
+    use crate::read_yaml::load_yaml;
+    use crate::test_util::temp_dir;
+    use super::Project;
+
+    /// Writes the files under a new directory and renders the config init writes for it
+    fn init(name: &str, files: &[(&str, &str)]) -> String {
+        let dir = temp_dir(name);
+        for (path, content) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        let project = Project::scan(&dir);
+        let _ = fs::remove_dir_all(&dir);
+        project.unwrap().render()
+    }
+
+    /// Messages of every diagnostic the config gets when read strictly
+    fn diagnostics(config: &str) -> Vec<String> {
+        let docs = load_yaml(config).ok().unwrap();
+        let mut diagnostics = Diagnostics::new(true);
+        Config::read(&docs[0], &mut diagnostics);
+        diagnostics.iter().map(|diag| diag.error.to_string()).collect()
+    }
+
+    #[test]
+    fn executable_project() {
+        let config = init("init-exec", &[
+            ("src/main.c", "int main(void) {\n    return 0;\n}\n"),
+            ("src/util.c", "int add(int a, int b) {return a + b;}\n"),
+            ("include/util.h", "int add(int a, int b);\n"),
+            ("libs/libfoo.a", "")
+        ]);
+        assert_eq!(diagnostics(&config), Vec::<String>::new(), "{config}");
+        assert!(config.contains("\nkind: c\n"), "{config}");
+        assert!(config.contains("\nsrc_dir: src\n"), "{config}");
+        assert!(config.contains("\ninclude: [include]\n"), "{config}");
+        assert!(config.contains("\nlibs: [foo]\n"), "{config}");
+        assert!(config.contains("\nmain: src/main.c\n"), "{config}");
+    }
+
+    #[test]
+    fn mixed_project_with_several_executables() {
+        let config = init("init-mixed", &[
+            ("server.cpp", "int main() {}\n"),
+            ("client.cpp", "int main() {}\n"),
+            ("net.cc", "void send() {}\n"),
+            ("checksum.c", "int sum() {return 0;}\n")
+        ]);
+        assert_eq!(diagnostics(&config), Vec::<String>::new(), "{config}");
+        assert!(config.contains("\nkind: [cpp, c]\n"), "{config}");
+        assert!(config.contains("\nsrc_ext: [cpp, c, cc]\n"), "{config}");
+        assert!(config.contains("\nexecutables:\n  - name: client\n    main: client.cpp\n"), "{config}");
+    }
+
+    #[test]
+    fn library_and_empty_projects() {
+        let config = init("init-lib", &[("src/list.c", "void push() {}\n")]);
+        assert_eq!(diagnostics(&config), Vec::<String>::new(), "{config}");
+        assert!(config.contains("\ntype: static_lib\n"), "{config}");
+
+        let config = init("init-empty", &[]);
+        assert_eq!(diagnostics(&config), Vec::<String>::new(), "{config}");
+        assert!(config.contains("\nsrc_dir: src\n"), "{config}");
+    }
+}
@@ -0,0 +1,112 @@
+use std::fmt::{Display, Write};
+
+/// A JSON value, built by hand to print machine-readable output
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>)
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::String(value.to_owned())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::String(value)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Json::Bool(value)
+    }
+}
+
+impl From<i64> for Json {
+    fn from(value: i64) -> Self {
+        Json::Number(value)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(value: Vec<T>) -> Self {
+        Json::Array(value.into_iter().map(Into::into).collect())
+    }
+}
+
+fn write_escaped(f: &mut impl Write, str: &str) -> std::fmt::Result {
+    f.write_char('"')?;
+    for c in str.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?
+        }
+    }
+    f.write_char('"')
+}
+
+impl Json {
+    /// Writes the value, spread over several indented lines if `indent` is set
+    fn write(&self, f: &mut std::fmt::Formatter<'_>, indent: Option<usize>) -> std::fmt::Result {
+        let newline = |f: &mut std::fmt::Formatter<'_>, level: usize| match indent {
+            Some(_) => write!(f, "\n{}", "  ".repeat(level)),
+            None => Ok(())
+        };
+        let level = indent.unwrap_or(0);
+        let inner = indent.map(|level| level + 1);
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(b) => write!(f, "{b}"),
+            Json::Number(n) => write!(f, "{n}"),
+            Json::String(str) => write_escaped(f, str),
+            Json::Array(values) if values.is_empty() => f.write_str("[]"),
+            Json::Array(values) => {
+                f.write_char('[')?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {f.write_char(',')?}
+                    newline(f, level + 1)?;
+                    value.write(f, inner)?;
+                }
+                newline(f, level)?;
+                f.write_char(']')
+            },
+            Json::Object(fields) if fields.is_empty() => f.write_str("{}"),
+            Json::Object(fields) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {f.write_char(',')?}
+                    newline(f, level + 1)?;
+                    write_escaped(f, key)?;
+                    f.write_str(if indent.is_some() {": "} else {":"})?;
+                    value.write(f, inner)?;
+                }
+                newline(f, level)?;
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+/// Compact on one line, or indented with the alternate flag (`{:#}`)
+impl Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write(f, f.alternate().then_some(0))
+    }
+}
@@ -1,22 +1,38 @@
-use std::{fmt::Display, fs, process::exit};
+use std::{env, fs, io, path::Path, process::exit};
 
-use config::Config;
+use config::{Config, Invocation, STDIO};
+use diff::unified_diff;
+use diagnostics::{Diagnostics, Message, MessageFormat, Severity};
 use gumdrop::Options;
-use read_yaml::{get_doc, read_yaml_file};
+use options::Action;
+use read_yaml::{check_merge_keys, doc_name, load_yaml, read_source, select_docs, ReadError};
+use source_map::{SourceMap, Snippet};
+use vars::EnvRefs;
 
 mod config;
+mod diagnostics;
+mod diff;
+mod extends;
+mod init;
+mod json;
 mod read_yaml;
 mod options;
 mod override_yaml;
+mod source_map;
+#[cfg(test)]
+mod test_util;
+mod vars;
 
-fn handle_read_error(err: &dyn Display) -> ! {
-    println!("Can't read config file : {err}");
+fn handle_read_error(format: MessageFormat, err: &ReadError, snippet: Option<Snippet>) -> ! {
+    Message::new(Severity::Error, err.code(), err.message()).with_snippet(snippet).print(format, "Can't read config file : ");
     exit(1);
 }
 
-fn handle_generic_error(err: &dyn Display) -> ! {
-    println!("Error : {err}");
-    exit(1);
+fn report_diagnostics(format: MessageFormat, diagnostics: &Diagnostics, source_map: &SourceMap) {
+    for diag in diagnostics.iter() {
+        let snippet = diag.error.node().and_then(|node| source_map.snippet(node));
+        Message::from_error(diag.severity, &diag.error).with_snippet(snippet).print(format, &format!("{} : ", diag.severity));
+    }
 }
 
 const INPUT_FILENAMES : [&str ; 4] = [
@@ -26,53 +42,295 @@ const INPUT_FILENAMES : [&str ; 4] = [
     "./goombuild.yml"
 ];
 
-fn find_input_file(input_filename: &Option<String>) -> &str{
+fn find_input_file(input_filename: &Option<String>) -> Result<&str, String> {
     match input_filename {
+        Some(str) if str == STDIO => Ok(str),
         Some(str) => {
             match fs::exists(str){
-                Ok(true) => str,
-                other => {
-                    match other {
-                        Err(err) => println!("Couldn't find input file :  {err}"),
-                        Ok(_) => println!("Couldn't find input file")
-                    }
-                    exit(1);
-                }
+                Ok(true) => Ok(str),
+                Err(err) => Err(format!("Couldn't find input file :  {err}")),
+                Ok(_) => Err("Couldn't find input file".to_owned())
             }
         },
         None => {
             let mut found_filename = None;
             for filename_ in INPUT_FILENAMES {
-                match fs::exists(filename_) {
-                    Ok(true) => found_filename = Some(filename_),
-                    _ => ()
-                }
+                if let Ok(true) = fs::exists(filename_) { found_filename = Some(filename_) }
             }
-            found_filename.unwrap_or_else(|| {
-                print!("Can't open config file : looked for any of ");
-                for path in &INPUT_FILENAMES[..INPUT_FILENAMES.len() - 1] {print!("{path}, ")}
-                println!("or {}", match &INPUT_FILENAMES.last(){Some(str)=>str,None=>unreachable!()});
-                exit(1);
+            found_filename.ok_or_else(|| {
+                let (last, others) = INPUT_FILENAMES.split_last().unwrap_or_else(|| unreachable!());
+                format!("Can't open config file : looked for any of {}, or {last}", others.join(", "))
             })
         }
     }
     
 }
 
+/// Writes a config for the project in the current directory, in place of the existing one if forced
+fn init_config(format: MessageFormat, force: bool) {
+    let existing = find_input_file(&None).ok();
+    if let (Some(existing), false) = (existing, force) {
+        Message::new(Severity::Error, "exists", format!("{existing} already exists, use --force to replace it")).print(format, "Error : ");
+        exit(1)
+    }
+    let project = init::Project::scan(Path::new(".")).unwrap_or_else(|err| {
+        Message::new(Severity::Error, "io", err).print(format, "Couldn't look through the project : ");
+        exit(1)
+    });
+    let filename = existing.unwrap_or(INPUT_FILENAMES[0]);
+    match fs::write(filename, project.render()) {
+        Ok(()) => eprintln!("Wrote {filename} for a {}", project.summary()),
+        Err(err) => {
+            Message::new(Severity::Error, "io", err).print(format, &format!("Couldn't write config file at path {filename} : "));
+            exit(1)
+        }
+    }
+}
+
+/// Arguments reproducing this run for one document, for its Makefile to regenerate itself
+fn invocation_args<'a>(options: &'a options::Options, document: Option<&'a str>) -> Vec<&'a str> {
+    let mut args = Vec::new();
+    if let Some(input_file) = &options.input_file {
+        args.extend(["--input-file", input_file]);
+    }
+    if let Some(document) = document {
+        args.extend(["--document", document]);
+    }
+    if let Some(out_file) = &options.out_file {
+        args.extend(["--out-file", out_file]);
+    }
+    if options.strict {
+        args.push("--strict");
+    }
+    if options.env_refs == EnvRefs::Make {
+        args.extend(["--env-refs", "make"]);
+    }
+    for ov in options.config_overrides.iter() {
+        args.extend(["--config-override", ov]);
+    }
+    args
+}
+
+/// Compares the Makefile on disk with the one the config generates, without writing it.
+/// Returns whether it is up to date, always true when only printing the diff
+fn check_makefile(format: MessageFormat, config: &Config, out_file: &str, invocation: &Invocation, check: bool, diff: bool) -> bool {
+    let generated = config.render(out_file, invocation).unwrap_or_else(|err| {
+        Message::new(Severity::Error, "io", err).print(format, "Couldn't generate Makefile : ");
+        exit(1)
+    });
+    let current = match fs::read_to_string(out_file) {
+        Ok(current) => current,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => {
+            Message::new(Severity::Error, "io", err).print(format, &format!("Couldn't read Makefile at path {out_file} : "));
+            exit(1)
+        }
+    };
+    if diff {
+        print!("{}", unified_diff(&current, &generated, out_file, &format!("{out_file} (generated)"), 3));
+    }
+    if check {
+        if current == generated {
+            eprintln!("{out_file} is up to date");
+        } else {
+            Message::new(Severity::Error, "out-of-date", format!("{out_file} is out of date, rerun goombuild to regenerate it")).print(format, "Error : ");
+            return false
+        }
+    }
+    true
+}
+
+/// Removes the Makefile at `out_file`, if it is the one the config generates (or if forced)
+fn clean_generated(format: MessageFormat, config: &Config, out_file: &str, invocation: &Invocation, force: bool) {
+    let current = match fs::read_to_string(out_file) {
+        Ok(current) => current,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            eprintln!("{out_file} doesn't exist, nothing to remove");
+            return
+        },
+        Err(err) => {
+            Message::new(Severity::Error, "io", err).print(format, &format!("Couldn't read Makefile at path {out_file} : "));
+            exit(1)
+        }
+    };
+    if !force {
+        let generated = config.render(out_file, invocation).unwrap_or_else(|err| {
+            Message::new(Severity::Error, "io", err).print(format, "Couldn't generate Makefile : ");
+            exit(1)
+        });
+        if current != generated {
+            Message::new(Severity::Error, "modified", format!("{out_file} isn't the Makefile goombuild would write, use --force to remove it anyway")).print(format, "Error : ");
+            exit(1)
+        }
+    }
+    match fs::remove_file(out_file) {
+        Ok(()) => eprintln!("Removed {out_file}"),
+        Err(err) => {
+            Message::new(Severity::Error, "io", err).print(format, &format!("Couldn't remove Makefile at path {out_file} : "));
+            exit(1)
+        }
+    }
+}
+
 fn main() {
-    let options = options::Options::parse_args_default_or_exit();
+    let args: Vec<String> = env::args().collect();
+    let mut options = options::Options::parse_args_default(&args[1..]).unwrap_or_else(|err| {
+        eprintln!("{}: {err}", args[0]);
+        exit(2)
+    });
+    if options.help_requested() {
+        eprint!("{}", options::usage(&args[0], &options));
+        exit(0)
+    }
+    let action = options.take_action();
     //println!("{options:?}");
 
-    let filename = find_input_file(&options.input_file);
+    let format = options.message_format;
+    if let Action::Init{force} = action {
+        init_config(format, force);
+        return
+    }
+    let filename = find_input_file(&options.input_file).unwrap_or_else(|msg| {
+        Message::new(Severity::Error, "input-file", msg).print(format, "");
+        exit(1)
+    });
+
+    //name of the config file in messages
+    let display_name = if filename == STDIO {"<stdin>"} else {filename};
+    let source = read_source(filename).unwrap_or_else(|err| handle_read_error(format, &err, None));
+    let mut docs = load_yaml(&source).unwrap_or_else(|err| {
+        let snippet = err.position().map(|mark| Snippet::new(display_name, &source, mark));
+        handle_read_error(format, &err, snippet)
+    });
+    let selected = select_docs(&docs, options.document.as_deref()).unwrap_or_else(|err| handle_read_error(format, &err, None));
+    //how the Makefile of each document selects it when regenerating itself, by name or else by index
+    let selectors: Vec<Option<String>> = selected.iter().map(|&index| {
+        (docs.len() > 1).then(|| doc_name(&docs[index]).map_or(index.to_string(), str::to_owned))
+    }).collect();
 
-    let mut docs = read_yaml_file(&filename).unwrap_or_else(|err| handle_read_error(&err));
-    let data = get_doc(&mut docs).unwrap_or_else(|err| handle_read_error(&err));
-    options.config_overrides.apply(data).unwrap_or_else(|err| handle_generic_error(&err));
-    //println!("{data:?}");
+    //each document is read on its own, as if it were a config file of its own
+    let mut diagnostics: Vec<Diagnostics> = selected.iter().map(|_| Diagnostics::new(options.strict)).collect();
+    let mut base_files = Vec::new();
+    for (&index, diagnostics) in selected.iter().zip(&mut diagnostics) {
+        let data = &mut docs[index];
+        base_files.push(extends::extend(data, display_name, diagnostics).unwrap_or_else(|err| {
+            let snippet = err.source.as_deref().and_then(|source| err.error.position().map(|mark| Snippet::new(&err.path, source, mark)));
+            Message::new(Severity::Error, err.error.code(), err.error.message()).with_snippet(snippet)
+                .print(format, &format!("Can't read config file {} extended by {} : ", err.path, err.extended_by));
+            exit(1)
+        }));
+        options.config_overrides.apply(data).unwrap_or_else(|err| {
+            Message::from_error(Severity::Error, &err).print(format, "Error : ");
+            exit(1)
+        });
+    }
+    //println!("{docs:?}");
 
-    let config = Config::read(data).unwrap_or_else(|err| {
-        println!("Incorrect config content : {err}");
+    //positions are found before variables change the strings they could be matched by
+    let source_maps: Vec<SourceMap> = selected.iter().zip(&base_files).map(|(&index, bases)| {
+        let mut source_map = SourceMap::new(display_name, &source, index, &docs[index]);
+        for base in bases.iter().rev() {
+            source_map.add(&base.path, &base.source, 0, &docs[index]);
+        }
+        source_map
+    }).collect();
+    let env_defaults: Vec<Vec<(String, String)>> = selected.iter().zip(&mut diagnostics)
+        .map(|(&index, diagnostics)| vars::interpolate(&mut docs[index], options.env_refs, diagnostics))
+        .collect();
+    let docs = &docs;
+    let mut configs = Vec::new();
+    for (i, env_defaults) in env_defaults.into_iter().enumerate() {
+        check_merge_keys(&docs[selected[i]], "", &mut diagnostics[i]);
+        let mut config = Config::read(&docs[selected[i]], &mut diagnostics[i]);
+        config.env_defaults = env_defaults;
+        report_diagnostics(format, &diagnostics[i], &source_maps[i]);
+        configs.push(config);
+    }
+    if diagnostics.iter().any(Diagnostics::has_errors) {
         exit(2)
-    });
-    config.write(options.out_file.as_ref().map_or(config.output_file.unwrap_or("./Makefile"), String::as_str));
+    }
+    if let Action::Sources{tests} = action {
+        for (i, config) in configs.iter().enumerate() {
+            //the sources of several documents are told apart by a header
+            if configs.len() > 1 {
+                let selector = selectors[i].as_deref().unwrap_or_default();
+                println!("{}# document {selector}", if i > 0 {"\n"} else {""});
+            }
+            let files = if tests {config.test_source_files()} else {config.source_files()};
+            match files {
+                Ok(files) => for file in files {
+                    println!("{file}");
+                },
+                Err(err) => {
+                    Message::new(Severity::Error, "io", err).print(format, "Couldn't list sources : ");
+                    exit(1)
+                }
+            }
+        }
+        return
+    }
+    for config in &mut configs {
+        match config.check_object_collisions() {
+            Ok(collisions) => for collision in collisions {
+                Message::new(Severity::Warning, "object-collision", collision).print(format, "Warning : object name collision : ");
+            },
+            Err((collisions, hint)) => {
+                for collision in collisions {
+                    Message::new(Severity::Error, "object-collision", collision).print(format, "Object name collision : ");
+                }
+                Message::new(Severity::Note, "object-collision", hint).print(format, "");
+                exit(2)
+            }
+        }
+    }
+    if options.out_file.is_some() && configs.len() > 1 {
+        Message::new(Severity::Error, "usage", "--out-file can't be given to several documents, select one with --document").print(format, "Error : ");
+        exit(1)
+    }
+    let out_files: Vec<&str> = configs.iter()
+        .map(|config| options.out_file.as_ref().map_or(config.output_file.unwrap_or("./Makefile"), String::as_str))
+        .collect();
+    if let Action::Dump(dump_format) = action {
+        let dumps: Vec<(&Config, &str)> = configs.iter().zip(out_files.iter().copied()).collect();
+        println!("{}", config::dump_all(&dumps, dump_format));
+        return
+    }
+    //the Makefiles of several documents would overwrite each other
+    for (i, out_file) in out_files.iter().enumerate() {
+        if let Some(first) = out_files[..i].iter().position(|other| other == out_file) {
+            let label = |i: usize| selectors[i].as_deref().unwrap_or_default().to_owned();
+            Message::new(Severity::Error, "output-file", format!("Documents {} and {} both write to {out_file}, give each its own output_file", label(first), label(i))).print(format, "Error : ");
+            exit(2)
+        }
+    }
+    if matches!(action, Action::Check{..} | Action::CleanGenerated{..}) && out_files.contains(&STDIO) {
+        Message::new(Severity::Error, "usage", "check, --diff and clean-generated work on a Makefile on disk, they can't be used with stdout").print(format, "Error : ");
+        exit(1)
+    }
+
+    let mut up_to_date = true;
+    for (i, config) in configs.iter().enumerate() {
+        let invocation = Invocation {
+            input_file: filename,
+            base_files: base_files[i].iter().map(|base| base.path.as_str()).collect(),
+            args: invocation_args(&options, selectors[i].as_deref())
+        };
+        let out_file = out_files[i];
+        match action {
+            Action::Check{check, diff} => up_to_date &= check_makefile(format, config, out_file, &invocation, check, diff),
+            Action::CleanGenerated{force} => clean_generated(format, config, out_file, &invocation, force),
+            Action::Generate => match config.write(out_file, &invocation) {
+                Ok(()) if out_file == STDIO => (),
+                Ok(()) => eprintln!("Successfully wrote config to {out_file}"),
+                Err(err) => {
+                    Message::new(Severity::Error, "io", err).print(format, &format!("Couldn't write Makefile at path {out_file} : "));
+                    exit(1)
+                }
+            },
+            Action::Init{..} | Action::Sources{..} | Action::Dump(_) => unreachable!("handled before the Makefile is needed")
+        }
+    }
+    if !up_to_date {
+        exit(1)
+    }
 }
@@ -1,5 +1,8 @@
-use crate::override_yaml::Overrides;
+use std::mem;
 
+use crate::{config::DumpFormat, diagnostics::MessageFormat, override_yaml::Overrides, vars::EnvRefs};
+
+/// Generates a Makefile from a YAML build config. Options given before a command apply to it, with no command goombuild runs `generate`
 #[derive(gumdrop::Options, Debug)]
 pub struct Options {
     pub help: bool,
@@ -8,7 +11,245 @@ pub struct Options {
 
     pub input_file: Option<String>,
 
-    #[options(multi="add", long="config-override")]
+    #[options(no_short, meta = "NAME|INDEX", help = "only use this document of a config file holding several (by name, or index from 0)")]
+    pub document: Option<String>,
+
+    #[options(help = "report unknown config keys as errors")]
+    pub strict: bool,
+
+    #[options(no_short, help = "exit with an error if the Makefile on disk isn't up to date, without writing it")]
+    pub check: bool,
+
+    #[options(no_short, help = "print the changes that would be made to the Makefile, without writing it")]
+    pub diff: bool,
+
+    #[options(no_short, meta = "yaml|json", help = "print the effective config instead of writing the Makefile")]
+    pub dump_config: Option<DumpFormat>,
+
+    #[options(no_short, meta = "human|json", help = "how to print errors and warnings (json goes to stderr)")]
+    pub message_format: MessageFormat,
+
+    #[options(no_short, meta = "resolve|make", help = "replace ${env:NAME} with its value, or with $(NAME) for make to expand")]
+    pub env_refs: EnvRefs,
+
+    #[options(multi="add", long="config-override", meta = "PATH=VALUE", help = "set a config value, e.g. std=c++20")]
+    pub config_overrides: Overrides,
+
+    #[options(command)]
+    pub command: Option<Command>
+}
+
+#[derive(gumdrop::Options, Debug)]
+pub enum Command {
+    #[options(help = "write the Makefile described by the config (default)")]
+    Generate(GenerateOptions),
+    #[options(help = "write a starting gbuild.yaml for the project in the current directory")]
+    Init(InitOptions),
+    #[options(help = "exit with an error if the Makefile on disk isn't up to date")]
+    Check(CheckOptions),
+    #[options(help = "print the effective config, with every default applied")]
+    Dump(DumpOptions),
+    #[options(help = "list the source files the Makefile would build")]
+    Sources(SourcesOptions),
+    #[options(help = "remove the Makefile written by generate")]
+    CleanGenerated(CleanGeneratedOptions)
+}
+
+/// Options of the commands that read the config, merged into the top-level ones
+#[derive(Debug, Default)]
+pub struct InputOptions {
+    pub input_file: Option<String>,
+    pub document: Option<String>,
+    pub strict: bool,
+    pub message_format: Option<MessageFormat>,
+    pub env_refs: Option<EnvRefs>,
     pub config_overrides: Overrides
 }
 
+/// Declares the options of a command that reads the config : its own ones, then those of `InputOptions`
+macro_rules! command_options {
+    ($name:ident { $($fields:tt)* }) => {
+        #[derive(gumdrop::Options, Debug)]
+        pub struct $name {
+            pub help: bool,
+
+            $($fields)*
+
+            #[options(help = "config file to read (- for stdin)")]
+            pub input_file: Option<String>,
+
+            #[options(no_short, meta = "NAME|INDEX", help = "only use this document of a config file holding several (by name, or index from 0)")]
+            pub document: Option<String>,
+
+            #[options(help = "report unknown config keys as errors")]
+            pub strict: bool,
+
+            #[options(no_short, meta = "human|json", help = "how to print errors and warnings (json goes to stderr)")]
+            pub message_format: Option<MessageFormat>,
+
+            #[options(no_short, meta = "resolve|make", help = "replace ${env:NAME} with its value, or with $(NAME) for make to expand")]
+            pub env_refs: Option<EnvRefs>,
+
+            #[options(multi="add", long="config-override", meta = "PATH=VALUE", help = "set a config value, e.g. std=c++20")]
+            pub config_overrides: Overrides
+        }
+
+        impl $name {
+            fn take_input(&mut self) -> InputOptions {
+                InputOptions {
+                    input_file: self.input_file.take(),
+                    document: self.document.take(),
+                    strict: self.strict,
+                    message_format: self.message_format.take(),
+                    env_refs: self.env_refs.take(),
+                    config_overrides: mem::take(&mut self.config_overrides)
+                }
+            }
+        }
+    };
+}
+
+command_options!(GenerateOptions {
+    #[options(help = "path of the Makefile to write (- for stdout)")]
+    pub out_file: Option<String>,
+});
+
+#[derive(gumdrop::Options, Debug)]
+pub struct InitOptions {
+    pub help: bool,
+
+    #[options(help = "overwrite an existing config file")]
+    pub force: bool,
+
+    #[options(no_short, meta = "human|json", help = "how to print errors and warnings (json goes to stderr)")]
+    pub message_format: Option<MessageFormat>
+}
+
+command_options!(CheckOptions {
+    #[options(help = "path of the Makefile to compare")]
+    pub out_file: Option<String>,
+
+    #[options(help = "also print the changes that generate would make")]
+    pub diff: bool,
+});
+
+command_options!(DumpOptions {
+    #[options(meta = "yaml|json", help = "format of the printed config (default: yaml)")]
+    pub format: Option<DumpFormat>,
+
+    #[options(help = "path of the Makefile, as shown in output_file")]
+    pub out_file: Option<String>,
+});
+
+command_options!(SourcesOptions {
+    #[options(help = "list the test sources instead")]
+    pub tests: bool,
+});
+
+command_options!(CleanGeneratedOptions {
+    #[options(help = "path of the Makefile to remove")]
+    pub out_file: Option<String>,
+
+    #[options(help = "remove the Makefile even if it doesn't match what generate would write")]
+    pub force: bool,
+});
+
+/// What a run of goombuild does, once the command and the old-style flags are sorted out
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Generate,
+    Init{force: bool},
+    Check{check: bool, diff: bool},
+    Dump(DumpFormat),
+    Sources{tests: bool},
+    CleanGenerated{force: bool}
+}
+
+impl Options {
+    /// Moves the options of the command into the top-level ones, that apply to every command,
+    /// and returns what to do
+    pub fn take_action(&mut self) -> Action {
+        let Some(command) = self.command.take() else {
+            //flags of the time before commands
+            return match self.dump_config {
+                Some(format) => Action::Dump(format),
+                None if self.check || self.diff => Action::Check{check: self.check, diff: self.diff},
+                None => Action::Generate
+            }
+        };
+        let (action, out_file, input) = match command {
+            Command::Generate(mut opts) => (Action::Generate, opts.out_file.take(), opts.take_input()),
+            Command::Init(opts) => (Action::Init{force: opts.force}, None, InputOptions{message_format: opts.message_format, ..Default::default()}),
+            Command::Check(mut opts) => (Action::Check{check: true, diff: opts.diff}, opts.out_file.take(), opts.take_input()),
+            Command::Dump(mut opts) => (Action::Dump(opts.format.unwrap_or(DumpFormat::Yaml)), opts.out_file.take(), opts.take_input()),
+            Command::Sources(mut opts) => (Action::Sources{tests: opts.tests}, None, opts.take_input()),
+            Command::CleanGenerated(mut opts) => (Action::CleanGenerated{force: opts.force}, opts.out_file.take(), opts.take_input())
+        };
+        self.out_file = out_file.or(self.out_file.take());
+        self.input_file = input.input_file.or(self.input_file.take());
+        self.document = input.document.or(self.document.take());
+        self.strict |= input.strict;
+        self.message_format = input.message_format.unwrap_or(self.message_format);
+        self.env_refs = input.env_refs.unwrap_or(self.env_refs);
+        for ov in input.config_overrides.iter() {
+            self.config_overrides.add(ov.to_owned());
+        }
+        action
+    }
+}
+
+/// Usage examples shown in the help of each command
+fn examples(command: Option<&str>) -> &'static [&'static str] {
+    match command {
+        None | Some("generate") => &[
+            "goombuild                                 write ./Makefile from ./gbuild.yaml",
+            "goombuild generate -i app.yaml -o app.mk  use other paths",
+            "goombuild generate -o - | make -f -       build without writing a Makefile",
+            "goombuild -c debug_info=true              override a config value for this run",
+            "goombuild --env-refs make                 let make expand ${env:NAME} from its environment",
+            "goombuild --document linux                only write the Makefile of the document named linux"
+        ],
+        Some("init") => &[
+            "goombuild init          write gbuild.yaml for the project in the current directory",
+            "goombuild init --force  replace the existing config file"
+        ],
+        Some("check") => &[
+            "goombuild check         fail if ./Makefile needs to be regenerated (e.g. in CI)",
+            "goombuild check --diff  also show what would change"
+        ],
+        Some("dump") => &[
+            "goombuild dump                print the effective config as YAML",
+            "goombuild dump --format json  print it as JSON"
+        ],
+        Some("sources") => &[
+            "goombuild sources                   list the project sources",
+            "goombuild sources --tests           list the test sources",
+            "goombuild sources --document linux  only list those of the document named linux"
+        ],
+        Some("clean-generated") => &[
+            "goombuild clean-generated          remove ./Makefile if goombuild wrote it",
+            "goombuild clean-generated --force  remove it even if it was edited since"
+        ],
+        Some(_) => &[]
+    }
+}
+
+/// Help of the command that was given (or of goombuild itself), with usage examples
+pub fn usage(program: &str, options: &Options) -> String {
+    let (command, self_usage, commands) = match &options.command {
+        Some(command) => (gumdrop::Options::command_name(command), gumdrop::Options::self_usage(command), None),
+        None => (None, gumdrop::Options::self_usage(options), <Options as gumdrop::Options>::command_list())
+    };
+    let mut usage = match command {
+        Some(name) => format!("Usage: {program} {name} [OPTIONS]\n\n{self_usage}\n"),
+        None => format!("Usage: {program} [OPTIONS] [COMMAND] [COMMAND OPTIONS]\n\n{self_usage}\n")
+    };
+    if let Some(commands) = commands {
+        usage.push_str(&format!("\nCommands:\n{commands}\n"));
+    }
+    usage.push_str("\nExamples:\n");
+    for example in examples(command) {
+        usage.push_str(&format!("  {example}\n"));
+    }
+    usage
+}
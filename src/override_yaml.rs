@@ -15,13 +15,17 @@ impl Overrides {
         self.overrides.push(ov)
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.overrides.iter().map(String::as_str)
+    }
+
     pub fn apply(&self, data: &mut Yaml) -> Result<(), ContextfulError> {
         for ov in &self.overrides {
             let split = ov.split_once("=");
             match split {
-                None => return Err(ContextfulError::from("Config override syntax is `path=value`").add_context(format!("In "))),
+                None => return Err(ContextfulError::from("Config override syntax is `path=value`").add_context(format!("In property override {ov}")).with_code("override")),
                 Some((path, value)) => {
-                    override_property(data, path, value).add_context(||format!("In property override {ov}"))?;
+                    override_property(data, path, value).add_context(||format!("In property override {ov}")).map_err(|err| err.with_code("override"))?;
                 }
             }
         };
@@ -75,7 +79,7 @@ fn traverse_path_existing<'a, 'b>(mut data: &'a mut Yaml, path: &'b[&str]) -> Re
 fn construct_from_path(path_fields: &[&str], value: &str) -> Result<Yaml, ContextfulError>{
     let mut value = create_value(value);
     for name in path_fields.iter().rev() {
-        match name.chars().nth(0) {
+        match name.chars().next() {
             None => return Err(ContextfulError::from("Empty name in the property path")),
             Some('+') => {
                 let mut new_value = Yaml::Array(Vec::new());
@@ -83,7 +87,7 @@ fn construct_from_path(path_fields: &[&str], value: &str) -> Result<Yaml, Contex
                 arr.push(value);
                 value = new_value;
             },
-            Some(c) if c.is_digit(10) => return Err(ContextfulError::from(format!("Cannot index element {name} in empty array"))),
+            Some(c) if c.is_ascii_digit() => return Err(ContextfulError::from(format!("Cannot index element {name} in empty array"))),
             Some(_) => {
                 let mut new_value = Yaml::Hash(yaml_rust2::yaml::Hash::new());
                 //println!("{new_value:?}");
@@ -100,18 +104,18 @@ fn construct_from_path(path_fields: &[&str], value: &str) -> Result<Yaml, Contex
  
 fn traverse_path(data: &mut Yaml, path: &[&str], value: &str) -> Result<(), ContextfulError> {
     if let Some(name) = path.first() {
-        match name.chars().nth(0) {
-            None => return Err(ContextfulError::from("Empty name in the property path")),
+        match name.chars().next() {
+            None => Err(ContextfulError::from("Empty name in the property path")),
             Some('+') => {
                 match data {
                     Yaml::Array(arr) => {
                         arr.push(construct_from_path(&path[1..], value)?);
-                        return Ok(())
+                        Ok(())
                     },
-                    _ => return Err(ContextfulError::from(format!("Trying to append element to non-list value (is a {})", yaml_type_name(&data))))
+                    _ => Err(ContextfulError::from(format!("Trying to append element to non-list value (is a {})", yaml_type_name(data))))
                 }
             },
-            Some(c) if c.is_digit(10) => {
+            Some(c) if c.is_ascii_digit() => {
                 match data {
                     Yaml::Array(arr) => {
                         let i: usize = name.parse().map_err(|err: std::num::ParseIntError|ContextfulError::from(format!("Couldn't parse list index {name} : {err}")))?;
@@ -132,7 +136,7 @@ fn traverse_path(data: &mut Yaml, path: &[&str], value: &str) -> Result<(), Cont
                         
 
                     },
-                    _ => return Err(ContextfulError::from(format!("Trying to index list element in non-list value (is a {}", yaml_type_name(&data))))
+                    _ => Err(ContextfulError::from(format!("Trying to index list element in non-list value (is a {}", yaml_type_name(data))))
                 }
             },
             Some(_) => {
@@ -153,7 +157,7 @@ fn traverse_path(data: &mut Yaml, path: &[&str], value: &str) -> Result<(), Cont
                         }
                         
                     }
-                    _ => return Err(ContextfulError::from(format!("Trying to index property in non-hash value (is a {}", yaml_type_name(&data))))
+                    _ => Err(ContextfulError::from(format!("Trying to index property in non-hash value (is a {}", yaml_type_name(data))))
                 }
             }
         }
@@ -165,7 +169,7 @@ fn traverse_path(data: &mut Yaml, path: &[&str], value: &str) -> Result<(), Cont
 pub fn override_property(data: &mut Yaml, path: &str, value: &str) -> Result<(), ContextfulError> {
     let split: Vec<&str> = path.split('.').collect();
 
-    traverse_path(data, &split.as_slice(), value)
+    traverse_path(data, split.as_slice(), value)
 }
 
 /*
@@ -1,14 +1,20 @@
-use std::{error::Error, fmt::Display, fs, io};
+use std::{error::Error, fmt::Display, fs, io::{self, Read}};
 
-use yaml_rust2::{ScanError, Yaml, YamlLoader};
+use yaml_rust2::{scanner::Marker, ScanError, Yaml, YamlLoader};
 use yaml_rust2::yaml::{Array, Hash};
 
+use crate::config::STDIO;
+use crate::diagnostics::{closest, Diagnostics};
+use crate::source_map::NodeId;
+
 
 #[derive(Debug)]
 pub enum ReadError {
     IO(io::Error),
     YamlScan(ScanError),
-    Content(&'static str)
+    Content(&'static str),
+    //no document matches --document
+    Document(String)
 }
 
 impl Error for ReadError {
@@ -29,11 +35,41 @@ impl Display for ReadError {
             },
             ReadError::Content(str) => {
                 f.write_str(str)
+            },
+            ReadError::Document(str) => {
+                f.write_str(str)
             }
         }
     }
 }
 
+impl ReadError {
+    /// Where the error is in the config file, for syntax errors
+    pub fn position(&self) -> Option<Marker> {
+        match self {
+            ReadError::YamlScan(scan_error) => Some(*scan_error.marker()),
+            _ => None
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ReadError::IO(_) => "io",
+            ReadError::YamlScan(_) => "yaml-syntax",
+            ReadError::Content(_) => "config-file",
+            ReadError::Document(_) => "document"
+        }
+    }
+
+    /// The error message, without its position
+    pub fn message(&self) -> String {
+        match self {
+            ReadError::YamlScan(scan_error) => scan_error.info().to_owned(),
+            _ => self.to_string()
+        }
+    }
+}
+
 impl From<io::Error> for ReadError {
     fn from(error: io::Error) -> Self {
         ReadError::IO(error)
@@ -52,24 +88,102 @@ impl From<&'static str> for ReadError {
     }
 }
 
-pub fn read_yaml_file(filename: &str) -> Result<Vec<Yaml>, ReadError> {
-    let raw_input = fs::read_to_string(filename)?;
-    let docs = YamlLoader::load_from_str(&raw_input)?;
+pub fn read_source(filename: &str) -> Result<String, ReadError> {
+    if filename == STDIO {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source)?;
+        return Ok(source)
+    }
+    Ok(fs::read_to_string(filename)?)
+}
+
+pub fn load_yaml(source: &str) -> Result<Vec<Yaml>, ReadError> {
+    let mut docs = YamlLoader::load_from_str(source)?;
+    for doc in &mut docs {
+        resolve_merge_keys(doc)?;
+    }
 
     Ok(docs)
 }
 
-pub fn get_doc(docs: &mut Vec<Yaml>) -> Result<&mut Yaml, &str> {
+/// Replaces the `<<` merge keys of every table with the entries of the tables they name (usually aliases).
+/// Keys set in the table itself win, then those of the first tables merged. Merge keys that aren't
+/// given tables are left for `check_merge_keys` to report, once their position is known
+fn resolve_merge_keys(yaml: &mut Yaml) -> Result<(), ReadError> {
+    match yaml {
+        Yaml::Array(array) => for yaml in array {
+            resolve_merge_keys(yaml)?;
+        },
+        Yaml::Hash(hash) => {
+            for (_, value) in hash.iter_mut() {
+                resolve_merge_keys(value)?;
+            }
+            let tables = match hash.get(&Yaml::from_str("<<")) {
+                Some(Yaml::Hash(_)) => true,
+                Some(Yaml::Array(tables)) => tables.iter().all(|table| matches!(table, Yaml::Hash(_))),
+                _ => false
+            };
+            if !tables {
+                return Ok(())
+            }
+            let tables = match hash.remove(&Yaml::from_str("<<")) {
+                Some(Yaml::Array(tables)) => tables,
+                table => table.into_iter().collect()
+            };
+            for table in tables {
+                let Yaml::Hash(table) = table else {continue};
+                for (key, value) in table {
+                    if !hash.contains_key(&key) {
+                        hash.insert(key, value);
+                    }
+                }
+            }
+        },
+        //aliases are replaced by a copy of their anchor when loading
+        Yaml::Alias(_) => return Err("Config file contains an alias that couldn't be resolved".into()),
+        _ => ()
+    }
+    Ok(())
+}
+
+pub fn get_doc(docs: &mut [Yaml]) -> Result<&mut Yaml, ReadError> {
 
     if docs.len() > 1{
-        return Err("Config file somehow contains multiple YAML documents");
+        return Err("Extended config files can't contain multiple YAML documents".into());
     }
     match docs.get_mut(0) {
         Some(v) => Ok(v),
-        None => Err("Empty config"),
+        None => Err("Empty config".into()),
     }
 }
 
+/// The `name` a document of the config file is selected by
+pub fn doc_name(doc: &Yaml) -> Option<&str> {
+    doc.as_hash()?.get(&Yaml::from_str("name"))?.as_str()
+}
+
+/// Indices of the documents to read : the one named by `selector` (or at that index, from 0), or all of them
+pub fn select_docs(docs: &[Yaml], selector: Option<&str>) -> Result<Vec<usize>, ReadError> {
+    if docs.is_empty() {
+        return Err("Empty config".into())
+    }
+    let Some(selector) = selector else {return Ok((0..docs.len()).collect())};
+    let named: Vec<usize> = (0..docs.len()).filter(|i| doc_name(&docs[*i]) == Some(selector)).collect();
+    match (named.as_slice(), selector.parse::<usize>()) {
+        ([i], _) => Ok(vec![*i]),
+        ([], Ok(i)) if i < docs.len() => Ok(vec![i]),
+        ([], Ok(i)) => Err(ReadError::Document(format!("There is no document {i}, the config file has {} (counted from 0)", docs.len()))),
+        ([], Err(_)) => {
+            let names: Vec<&str> = docs.iter().filter_map(doc_name).collect();
+            Err(ReadError::Document(if names.is_empty() {
+                format!("There is no document named {selector}, the documents have no name : select them by index, from 0")
+            } else {
+                format!("There is no document named {selector}, the documents are named {}", names.join(", "))
+            }))
+        },
+        _ => Err(ReadError::Document(format!("Several documents are named {selector}, give them different names")))
+    }
+}
 
 pub enum ContextfulErrorType {
     WrongType(&'static str, &'static str),
@@ -79,22 +193,70 @@ pub enum ContextfulErrorType {
 
 pub struct ContextfulError {
     err: ContextfulErrorType,
-    context: String
+    context: String,
+    //the YAML node the error is about, if known
+    node: Option<NodeId>,
+    code: Option<&'static str>
 }
 
 impl ContextfulError {
     pub fn _with_context<T: ToString>(err: ContextfulErrorType, context_str: impl Fn() -> T) -> Self {
-        Self{err, context: context_str().to_string()}
+        Self{err, context: context_str().to_string(), node: None, code: None}
     }
 
     pub fn wrong_type(expected: &'static str, got: &Yaml) -> Self {
-        Self{err: ContextfulErrorType::WrongType(expected, yaml_type_name(&got)), context: String::new()}
+        Self{err: ContextfulErrorType::WrongType(expected, yaml_type_name(got)), context: String::new(), node: None, code: None}.at(got)
     }
 
     pub fn add_context<T: ToString>(mut self, context: T) -> Self {
         self.context = context.to_string() + " " + self.context.as_str();
         self
     }
+
+    /// Points the error to a YAML node (or table or list), unless it already points to a more precise one
+    pub fn at<T>(mut self, node: &T) -> Self {
+        self.node.get_or_insert(NodeId::of(node));
+        self
+    }
+
+    /// Points the error to the value of a key of a table, if present
+    pub fn at_key(self, data: &Hash, key: &str) -> Self {
+        match get_data(data, key) {
+            Some(yaml) => self.at(yaml),
+            None => self
+        }
+    }
+
+    pub fn node(&self) -> Option<NodeId> {
+        self.node
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    /// What kind of error this is, for machine-readable output
+    pub fn code(&self) -> &'static str {
+        self.code.unwrap_or(match self.err {
+            ContextfulErrorType::WrongType(..) => "wrong-type",
+            _ => "invalid-value"
+        })
+    }
+
+    /// Where the error happened in the config, e.g. `In target debug cflags`
+    pub fn context(&self) -> &str {
+        self.context.trim_end()
+    }
+
+    /// The error without its context
+    pub fn message(&self) -> String {
+        match &self.err {
+            ContextfulErrorType::WrongType(expected, got) => format!("should be a {expected} (got {got})"),
+            ContextfulErrorType::Message(msg) => msg.to_string(),
+            ContextfulErrorType::Other(msg) => msg.clone()
+        }
+    }
 }
 
 pub trait ContextfulMaybe {
@@ -103,13 +265,13 @@ pub trait ContextfulMaybe {
 
 impl From<&'static str> for ContextfulError {
     fn from(error: &'static str) -> Self {
-        ContextfulError { err: ContextfulErrorType::Message (error), context: String::new() }
+        ContextfulError { err: ContextfulErrorType::Message (error), context: String::new(), node: None, code: None }
     }
 }
 
 impl From<String> for ContextfulError {
     fn from(error: String) -> Self {
-        ContextfulError {err: ContextfulErrorType::Other(error), context: String::new()}
+        ContextfulError {err: ContextfulErrorType::Other(error), context: String::new(), node: None, code: None}
     }
 }
 
@@ -126,44 +288,9 @@ impl<T> ContextfulMaybe for ContextfulResult<T>{
     }
 }
 
-pub enum ContentError {
-    Other(&'static str),
-    Contextful(ContextfulError)
-}
-
-impl From<&'static str> for ContentError {
-    fn from(error: &'static str) -> Self {
-        ContentError::Other (error)
-    }
-}
-
-impl From<ContextfulError> for ContentError {
-    fn from(value: ContextfulError) -> Self {
-        ContentError::Contextful(value)
-    }
-}
-
 impl Display for ContextfulError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.context)?;
-        match &self.err {
-            ContextfulErrorType::WrongType(expected, got) => {
-                write!(f, "should be a {expected} (got {got})")
-            },
-            ContextfulErrorType::Message(mdsg) => f.write_str(&mdsg),
-            ContextfulErrorType::Other(msg) => f.write_str(&msg)
-        }
-    }
-}
-
-impl Display for ContentError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match &self {
-            ContentError::Other(error) => f.write_str(&error),
-            ContentError::Contextful(err )=> {
-                err.fmt(f)
-            }
-        }
+        write!(f, "{}{}", self.context, self.message())
     }
 }
 
@@ -190,11 +317,56 @@ fn _to_unsinged(source: i64) -> Result<u64, ContextfulError> {
 
 pub fn handle_wrong_type(yaml_value: &Yaml, expected: &'static str) -> ContextfulError {
     match yaml_value {
-        Yaml::BadValue => " : Invalid YAML value".into(),
+        Yaml::BadValue => ContextfulError::from(" : Invalid YAML value").at(yaml_value),
         _ => ContextfulError::wrong_type(expected, yaml_value)
     }
 }
 
+/// Reports the merge keys (`<<`) left in the config, whose values weren't tables to merge.
+/// `path` is where `yaml` is in the config
+pub fn check_merge_keys(yaml: &Yaml, path: &str, diagnostics: &mut Diagnostics) {
+    let in_path = |key: &str| if path.is_empty() {key.to_owned()} else {format!("{path}.{key}")};
+    match yaml {
+        Yaml::Array(array) => for (i, yaml) in array.iter().enumerate() {
+            check_merge_keys(yaml, &in_path(&i.to_string()), diagnostics);
+        },
+        Yaml::Hash(hash) => for (key, value) in hash {
+            let key = match key {
+                Yaml::String(str) | Yaml::Real(str) => str.clone(),
+                Yaml::Integer(n) => n.to_string(),
+                _ => String::from("?")
+            };
+            if key == "<<" {
+                diagnostics.error(ContextfulError::from("Merge keys (<<) should be given a table or a list of tables").add_context(format!("In {}", in_path(&key))).at(value));
+            } else {
+                check_merge_keys(value, &in_path(&key), diagnostics);
+            }
+        },
+        _ => ()
+    }
+}
+
+/// Warns about the keys of a table that aren't any of `known`, suggesting the closest known key
+pub fn check_keys(data: &Hash, known: &[&[&str]], diagnostics: &mut Diagnostics) {
+    for key in data.keys() {
+        let name = match key {
+            Yaml::String(str) | Yaml::Real(str) => str.clone(),
+            Yaml::Integer(n) => n.to_string(),
+            Yaml::Boolean(b) => b.to_string(),
+            _ => yaml_type_name(key).to_owned()
+        };
+        //x- keys are free for the user, e.g. to hold anchors, and merge keys left are reported on their own
+        if name.starts_with("x-") || name == "<<" || known.iter().any(|keys| keys.contains(&name.as_str())) {
+            continue
+        }
+        let err = match closest(&name, known.iter().flat_map(|keys| keys.iter().copied())) {
+            Some(suggestion) => ContextfulError::from(format!("{name} is not a known key, did you mean {suggestion} ?")),
+            None => ContextfulError::from(format!("{name} is not a known key"))
+        };
+        diagnostics.warn(err.with_code("unknown-key").at(key));
+    }
+}
+
 pub fn get_data<'a>(data: &'a Hash, key: & str) -> Option<&'a Yaml> {
     data.get(&Yaml::from_str(key))
 }
@@ -205,24 +377,17 @@ pub fn get_data_mut<'a>(data: &'a mut Hash, key: &str) -> Option<&'a mut Yaml> {
 
 pub type YamlResult<T> = Result<Option<T>, ContextfulError>;
 
-pub fn try_map_option <T, U, F: Fn(T) -> Result<U, ContextfulError>> (opt: Option<T>, f: F) -> YamlResult<U> {
-    Ok(match opt {
-        None => None,
-        Some(v) => Some(f(v)?)
-    })
-}
-
 fn get_as<'a, T, F: Fn(&'a Yaml) -> YamlResult<T>>(extract: F, data: &'a Hash, key: &'static str) -> YamlResult<T> {
     match get_data(data, key){
         None => Ok(None),
-        Some(v) => extract(v).map_err(|err| err.add_context(key))
+        Some(v) => extract(v).map_err(|err| err.add_context(key).at(v))
     }
 }
 
 fn extract_str(yaml: &Yaml) -> YamlResult<&str> {
     match yaml {
         Yaml::String(str) => Ok(Some(str.as_str())),
-        _ => Err(handle_wrong_type(&yaml, "string"))
+        _ => Err(handle_wrong_type(yaml, "string"))
     }
 }
 pub fn get_str<'a>(data: &'a Hash, key: &'static str) -> YamlResult<&'a str>{get_as(extract_str, data, key)}
@@ -230,21 +395,30 @@ pub fn get_str<'a>(data: &'a Hash, key: &'static str) -> YamlResult<&'a str>{get
 fn extract_int(yaml: & Yaml) -> YamlResult<i64> {
     match yaml {
         Yaml::Integer(n) => Ok(Some(*n)),
-        _ => Err(handle_wrong_type(&yaml, "string"))
+        _ => Err(handle_wrong_type(yaml, "integer"))
     }
 }
 pub fn get_int(data: &Hash, key: &'static str) -> YamlResult<i64>{get_as(extract_int, data, key)}
 
+fn extract_str_or_int(yaml: &Yaml) -> YamlResult<String> {
+    match yaml {
+        Yaml::String(str) | Yaml::Real(str) => Ok(Some(str.clone())),
+        Yaml::Integer(n) => Ok(Some(n.to_string())),
+        _ => Err(handle_wrong_type(yaml, "string or number"))
+    }
+}
+pub fn get_str_or_int(data: &Hash, key: &'static str) -> YamlResult<String>{get_as(extract_str_or_int, data, key)}
+
 
 fn extract_bool(yaml: & Yaml) -> YamlResult<bool> {
     match yaml {
         Yaml::Boolean(b) => Ok(Some(*b)),
-        _ => Err(handle_wrong_type(&yaml, "string"))
+        _ => Err(handle_wrong_type(yaml, "boolean"))
     }
 }
 pub fn get_bool(data: &Hash, key: &'static str) -> YamlResult<bool>{get_as(extract_bool, data, key)}
 
-fn _extract_hash<'a>(yaml: &'a Yaml) -> Result<Option<&'a Hash>, ContextfulError> {
+fn _extract_hash(yaml: &Yaml) -> Result<Option<&Hash>, ContextfulError> {
     match yaml {
         Yaml::Hash(hash) => Ok(Some(hash)),
         val => Err(handle_wrong_type(val, "table"))
@@ -252,14 +426,18 @@ fn _extract_hash<'a>(yaml: &'a Yaml) -> Result<Option<&'a Hash>, ContextfulError
 }
 pub fn _get_hash<'a>(data: &'a Hash, key: &'static str) -> YamlResult<&'a Hash>{get_as(_extract_hash, data, key)}
 
-fn extract_array<'a>(yaml: &'a Yaml) -> Result<Option<&'a Array>, ContextfulError> {
+fn extract_array(yaml: &Yaml) -> Result<Option<&Array>, ContextfulError> {
     match yaml {
         Yaml::Array(array) => Ok(Some(array)),
-        val => Err(handle_wrong_type(&val, "array"))
+        val => Err(handle_wrong_type(val, "array"))
     }
 }
 pub fn get_array<'a>(data: &'a Hash, key: &'static str) -> YamlResult<&'a Array>{get_as(extract_array, data, key)}
 
+pub fn get_str_list<'a>(data: &'a Hash, key: &'static str) -> YamlResult<Vec<&'a str>>{
+    get_as(|yaml| array_or_string_into_vec(yaml).map(Some), data, key)
+}
+
 pub fn array_or_string_into_vec<'a>(yaml: &'a Yaml) -> Result<Vec<&'a str>, ContextfulError> {
     let mut vec = Vec::<&'a str>::new();
     match yaml {
@@ -276,4 +454,63 @@ pub fn array_or_string_into_vec<'a>(yaml: &'a Yaml) -> Result<Vec<&'a str>, Cont
         val => return Err(handle_wrong_type(val, "string or array thereof"))
     };
     Ok(vec)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use yaml_rust2::{Yaml, YamlLoader};
+
+    use crate::diagnostics::Diagnostics;
+    use crate::test_util::yaml;
+    use super::{check_merge_keys, load_yaml, select_docs};
+
+    fn load(source: &str) -> Yaml {
+        load_yaml(source).ok().unwrap().remove(0)
+    }
+
+    fn merge_key_errors(doc: &Yaml) -> Vec<String> {
+        let mut diagnostics = Diagnostics::new(false);
+        check_merge_keys(doc, "", &mut diagnostics);
+        diagnostics.iter().map(|diag| diag.error.to_string()).collect()
+    }
+
+    #[test]
+    fn merge_keys_are_resolved() {
+        let doc = load("x-base: &base {kind: c, std: [c11]}\nprofiles:\n  - <<: *base\n    name: debug\n    kind: cpp\n");
+        assert_eq!(doc["profiles"][0], yaml("{name: debug, kind: cpp, std: [c11]}"));
+        assert!(merge_key_errors(&doc).is_empty());
+    }
+
+    #[test]
+    fn first_merged_table_wins() {
+        let doc = load("x-a: &a {std: [c11], optimize: 2}\nx-b: &b {std: [c99], debug_info: true}\n<<: [*a, *b]\n");
+        assert_eq!(doc["std"], yaml("[c11]"));
+        assert_eq!(doc["optimize"], Yaml::Integer(2));
+        assert_eq!(doc["debug_info"], Yaml::Boolean(true));
+    }
+
+    #[test]
+    fn merge_keys_need_tables() {
+        let doc = load("kind: c\ntests:\n  dir: t\n  <<: 3\n");
+        let errors = merge_key_errors(&doc);
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(errors[0].starts_with("In tests.<<"), "{errors:?}");
+    }
+
+    #[test]
+    fn documents_are_selected_by_name_or_index() {
+        let docs = YamlLoader::load_from_str("name: linux\n---\nname: win\n---\nkind: c\n").unwrap();
+        assert_eq!(select_docs(&docs, None).ok(), Some(vec![0, 1, 2]));
+        assert_eq!(select_docs(&docs, Some("win")).ok(), Some(vec![1]));
+        assert_eq!(select_docs(&docs, Some("2")).ok(), Some(vec![2]));
+        let unknown = select_docs(&docs, Some("mac")).err().unwrap().to_string();
+        assert!(unknown.contains("named linux, win"), "{unknown}");
+        assert!(select_docs(&docs, Some("3")).is_err());
+    }
+
+    #[test]
+    fn document_names_must_be_unique() {
+        let docs = YamlLoader::load_from_str("name: a\n---\nname: a\n").unwrap();
+        assert!(select_docs(&docs, Some("a")).is_err());
+        assert!(select_docs(&[], None).is_err());
+    }
+}
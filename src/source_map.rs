@@ -0,0 +1,312 @@
+use std::{collections::HashMap, fmt::Display};
+
+use yaml_rust2::{parser::{Event, MarkedEventReceiver, Parser}, scanner::{Marker, TScalarStyle}, Yaml};
+
+/// Identifies a node of a YAML tree (or the table/list it holds) by its address,
+/// valid as long as the tree isn't modified
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    pub fn of<T>(node: &T) -> Self {
+        NodeId(node as *const T as usize)
+    }
+}
+
+/// A node as seen by the parser, with where it starts in the source
+struct MarkedNode {
+    mark: Marker,
+    kind: MarkedKind
+}
+
+enum MarkedKind {
+    Scalar(Yaml),
+    Sequence(Vec<MarkedNode>),
+    Mapping(Vec<(MarkedNode, MarkedNode)>),
+    Alias
+}
+
+/// Builds the marked nodes of every document from the parser events
+#[derive(Default)]
+struct Recorder {
+    docs: Vec<MarkedNode>,
+    stack: Vec<MarkedNode>,
+    //key waiting for its value, for each mapping of the stack
+    keys: Vec<Option<MarkedNode>>,
+    doc_start: usize
+}
+
+impl Recorder {
+    fn insert(&mut self, node: MarkedNode) {
+        let Some(parent) = self.stack.last_mut() else {
+            self.docs.push(node);
+            return
+        };
+        match &mut parent.kind {
+            MarkedKind::Sequence(items) => items.push(node),
+            MarkedKind::Mapping(entries) => {
+                let Some(key) = self.keys.last_mut() else {unreachable!("mapping without a key slot")};
+                match key.take() {
+                    None => *key = Some(node),
+                    Some(key) => entries.push((key, node))
+                }
+            },
+            _ => unreachable!("scalars and aliases can't have children")
+        }
+    }
+}
+
+impl MarkedEventReceiver for Recorder {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            Event::DocumentStart => self.doc_start = self.docs.len(),
+            //empty documents are loaded as a bad value
+            Event::DocumentEnd => if self.docs.len() == self.doc_start {
+                self.docs.push(MarkedNode{mark, kind: MarkedKind::Scalar(Yaml::BadValue)});
+            },
+            Event::SequenceStart(..) => self.stack.push(MarkedNode{mark, kind: MarkedKind::Sequence(Vec::new())}),
+            Event::MappingStart(..) => {
+                self.stack.push(MarkedNode{mark, kind: MarkedKind::Mapping(Vec::new())});
+                self.keys.push(None);
+            },
+            Event::SequenceEnd | Event::MappingEnd => {
+                if matches!(ev, Event::MappingEnd) {self.keys.pop();}
+                let Some(mut node) = self.stack.pop() else {return};
+                //block mappings are marked after their first key, point to the key instead
+                if let MarkedKind::Mapping(entries) = &node.kind {
+                    if let Some((key, _)) = entries.first().filter(|(key, _)| key.mark.index() < node.mark.index()) {
+                        node.mark = key.mark;
+                    }
+                }
+                self.insert(node);
+            },
+            Event::Scalar(value, style, ..) => {
+                //tagged scalars may be loaded differently, they just won't be found in the tree
+                let value = if style == TScalarStyle::Plain {Yaml::from_str(&value)} else {Yaml::String(value)};
+                self.insert(MarkedNode{mark, kind: MarkedKind::Scalar(value)});
+            },
+            Event::Alias(_) => self.insert(MarkedNode{mark, kind: MarkedKind::Alias}),
+            Event::Nothing | Event::StreamStart | Event::StreamEnd => ()
+        }
+    }
+}
+
+/// Where each node of a loaded config comes from in its source files
+pub struct SourceMap<'a> {
+    files: Vec<(&'a str, &'a str)>,
+    positions: HashMap<NodeId, (usize, Marker)>,
+    //items of each list already matched from its end, by `+key` appends
+    appended: HashMap<NodeId, usize>
+}
+
+impl <'a> SourceMap<'a> {
+    /// Parses the source again to find the position of the nodes of `doc`, the document at `index` in it.
+    /// Nodes that don't match the source (e.g. set by a config override) get no position
+    pub fn new(filename: &'a str, source: &'a str, index: usize, doc: &Yaml) -> Self {
+        let mut map = SourceMap{files: Vec::new(), positions: HashMap::new(), appended: HashMap::new()};
+        map.add(filename, source, index, doc);
+        map
+    }
+
+    /// Finds the position of the nodes of `doc` that come from the document at `index` of another file
+    /// merged into it, except those already found in the files added before
+    pub fn add(&mut self, filename: &'a str, source: &'a str, index: usize, doc: &Yaml) {
+        self.files.push((filename, source));
+        let mut recorder = Recorder::default();
+        //the source was already loaded successfully, so this can't fail
+        if Parser::new_from_str(source).load(&mut recorder, true).is_ok() {
+            if let Some(marked) = recorder.docs.get(index) {
+                self.align(doc, marked);
+            }
+        }
+    }
+
+    fn insert(&mut self, yaml: &Yaml, mark: Marker) {
+        let file = self.files.len() - 1;
+        self.positions.entry(NodeId::of(yaml)).or_insert((file, mark));
+        match yaml {
+            Yaml::Hash(hash) => {self.positions.entry(NodeId::of(hash)).or_insert((file, mark));},
+            Yaml::Array(array) => {self.positions.entry(NodeId::of(array)).or_insert((file, mark));},
+            _ => ()
+        }
+    }
+
+    fn align(&mut self, yaml: &Yaml, marked: &MarkedNode) {
+        match (yaml, &marked.kind) {
+            //an alias is loaded as a copy of its anchor, which is reported where it's used
+            (_, MarkedKind::Alias) => self.insert_all(yaml, marked.mark),
+            (Yaml::Hash(hash), MarkedKind::Mapping(entries)) => {
+                self.insert(yaml, marked.mark);
+                let mut merged = Vec::new();
+                for (key, value) in entries {
+                    let MarkedKind::Scalar(key_yaml) = &key.kind else {continue};
+                    if let Some((key_yaml, value_yaml)) = hash.get_key_value(key_yaml) {
+                        self.insert(key_yaml, key.mark);
+                        self.align(value_yaml, value);
+                    } else if let Some(list) = key_yaml.as_str().and_then(|key| key.strip_prefix('+')).and_then(|key| hash.get(&Yaml::from_str(key))) {
+                        self.align_appended(list, value);
+                    } else if key_yaml.as_str() == Some("<<") {
+                        merged.push(value);
+                    }
+                }
+                //after the keys set in the table itself, that take precedence
+                for value in merged {
+                    self.align_merged(yaml, value);
+                }
+            },
+            (Yaml::Array(array), MarkedKind::Sequence(items)) => {
+                self.insert(yaml, marked.mark);
+                for (yaml, item) in array.iter().zip(items) {
+                    self.align(yaml, item);
+                }
+            },
+            (_, MarkedKind::Scalar(value)) if value == yaml => self.insert(yaml, marked.mark),
+            //a single value that a `+key` of another file turned into the first item of a list
+            (Yaml::Array(array), MarkedKind::Scalar(_)) => if let Some(first) = array.first() {
+                self.align(first, marked);
+            },
+            _ => ()
+        }
+    }
+
+    /// Aligns the values of a `+key` with the end of the list they were appended to.
+    /// Files are added from the last merged, so their values come before those already aligned
+    fn align_appended(&mut self, list: &Yaml, marked: &MarkedNode) {
+        let Yaml::Array(array) = list else {return};
+        let items = match &marked.kind {
+            MarkedKind::Sequence(items) => items.iter().collect(),
+            _ => vec![marked]
+        };
+        let consumed = self.appended.entry(NodeId::of(list)).or_default();
+        let end = array.len().saturating_sub(*consumed);
+        let start = end.saturating_sub(items.len());
+        *consumed += items.len();
+        for (yaml, item) in array[start..end].iter().zip(items) {
+            self.align(yaml, item);
+        }
+    }
+
+    /// Aligns the value of a `<<` merge key with the table its entries were merged into
+    fn align_merged(&mut self, yaml: &Yaml, marked: &MarkedNode) {
+        let Yaml::Hash(hash) = yaml else {return};
+        match &marked.kind {
+            //the entries that aren't positioned yet come from the alias
+            MarkedKind::Alias => for (key, value) in hash {
+                if !self.positions.contains_key(&NodeId::of(key)) {
+                    self.insert_all(key, marked.mark);
+                    self.insert_all(value, marked.mark);
+                }
+            },
+            MarkedKind::Mapping(_) => self.align(yaml, marked),
+            //tables written inline are positioned first, as aliases can only claim what is left
+            MarkedKind::Sequence(items) => {
+                let (aliases, tables): (Vec<&MarkedNode>, Vec<&MarkedNode>) = items.iter().partition(|item| matches!(item.kind, MarkedKind::Alias));
+                for item in tables.into_iter().chain(aliases) {
+                    self.align_merged(yaml, item);
+                }
+            },
+            MarkedKind::Scalar(_) => ()
+        }
+    }
+
+    fn insert_all(&mut self, yaml: &Yaml, mark: Marker) {
+        self.insert(yaml, mark);
+        match yaml {
+            Yaml::Hash(hash) => for (key, value) in hash {
+                self.insert_all(key, mark);
+                self.insert_all(value, mark);
+            },
+            Yaml::Array(array) => for yaml in array {
+                self.insert_all(yaml, mark);
+            },
+            _ => ()
+        }
+    }
+
+    pub fn snippet(&self, node: NodeId) -> Option<Snippet<'a>> {
+        let (file, mark) = self.positions.get(&node)?;
+        let (filename, source) = self.files[*file];
+        Some(Snippet::new(filename, source, *mark))
+    }
+}
+
+/// A position in a source file, displayed with the line it's on and a caret under it, like rustc does
+pub struct Snippet<'a> {
+    filename: &'a str,
+    source: &'a str,
+    line: usize,
+    col: usize
+}
+
+impl <'a> Snippet<'a> {
+    pub fn new(filename: &'a str, source: &'a str, mark: Marker) -> Self {
+        Snippet{filename, source, line: mark.line(), col: mark.col()}
+    }
+
+    pub fn filename(&self) -> &str {
+        self.filename
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// 1-indexed, like the column displayed
+    pub fn column(&self) -> usize {
+        self.col + 1
+    }
+}
+
+impl Display for Snippet<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let line_number = self.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+        writeln!(f, "{gutter}--> {}:{}:{}", self.filename, self.line, self.column())?;
+        let Some(line) = self.source.lines().nth(self.line.saturating_sub(1)) else {return Ok(())};
+        //tabs are kept so that the caret lines up with the source
+        let padding: String = line.chars().take(self.col).map(|c| if c == '\t' {'\t'} else {' '}).collect();
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{line_number} | {line}")?;
+        write!(f, "{gutter} | {padding}^")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust2::Yaml;
+
+    use crate::test_util::yaml;
+    use super::{NodeId, SourceMap};
+
+    /// File and line (from 1) the node was found at
+    fn position(map: &SourceMap, node: &Yaml) -> Option<(String, usize)> {
+        map.snippet(NodeId::of(node)).map(|snippet| (snippet.filename().to_owned(), snippet.line()))
+    }
+
+    #[test]
+    fn values_are_positioned() {
+        let doc = yaml("kind: c\nlibs:\n  - m\n  - pthread\n");
+        let map = SourceMap::new("gbuild.yaml", "kind: c\nlibs:\n  - m\n  - pthread\n", 0, &doc);
+        assert_eq!(position(&map, &doc["kind"]), Some(("gbuild.yaml".to_owned(), 1)));
+        assert_eq!(position(&map, &doc["libs"][1]), Some(("gbuild.yaml".to_owned(), 4)));
+    }
+
+    #[test]
+    fn appended_values_come_from_each_file() {
+        //include: inc in the base file, +include: [extra, more] in the one extending it
+        let doc = yaml("include: [inc, extra, more]\n");
+        let mut map = SourceMap::new("gbuild.yaml", "kind: c\n+include:\n  - extra\n  - more\n", 0, &doc);
+        map.add("base.yaml", "include: inc\n", 0, &doc);
+        assert_eq!(position(&map, &doc["include"][0]), Some(("base.yaml".to_owned(), 1)));
+        assert_eq!(position(&map, &doc["include"][1]), Some(("gbuild.yaml".to_owned(), 3)));
+        assert_eq!(position(&map, &doc["include"][2]), Some(("gbuild.yaml".to_owned(), 4)));
+    }
+
+    #[test]
+    fn documents_are_found_by_index() {
+        let source = "name: a\nkind: c\n---\nname: b\nkind: cpp\n";
+        let doc = yaml("name: b\nkind: cpp\n");
+        let map = SourceMap::new("gbuild.yaml", source, 1, &doc);
+        assert_eq!(position(&map, &doc["kind"]), Some(("gbuild.yaml".to_owned(), 5)));
+    }
+}
@@ -0,0 +1,33 @@
+use std::{env, fs, path::PathBuf, process};
+
+use yaml_rust2::{Yaml, YamlLoader};
+
+use crate::config::{Config, Invocation};
+use crate::diagnostics::Diagnostics;
+
+/// First document of a YAML source, without goombuild's own loading steps
+pub fn yaml(source: &str) -> Yaml {
+    YamlLoader::load_from_str(source).unwrap().remove(0)
+}
+
+/// Makefile generated from a config that must have no errors
+pub fn render(yaml: &str) -> String {
+    render_to(yaml, "Makefile")
+}
+
+pub fn render_to(yaml: &str, filename: &str) -> String {
+    let doc = self::yaml(yaml);
+    let mut diagnostics = Diagnostics::new(true);
+    let config = Config::read(&doc, &mut diagnostics);
+    assert!(!diagnostics.has_errors());
+    let invocation = Invocation{input_file: "gbuild.yaml", base_files: Vec::new(), args: Vec::new()};
+    config.render(filename, &invocation).unwrap()
+}
+
+/// Empty directory for a test to write files in, its name should be unique among the tests
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("goombuild-{name}-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
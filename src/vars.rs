@@ -0,0 +1,352 @@
+use std::{collections::HashMap, env, str::FromStr};
+
+use yaml_rust2::{yaml::Hash, Yaml};
+
+use crate::diagnostics::Diagnostics;
+use crate::read_yaml::{get_data, handle_wrong_type, ContextfulError};
+
+/// Replaces the `${...}` references of a string with what `lookup` gives for their content.
+/// `$${` is kept as a literal `${`, for make or the shell to expand
+fn substitute(str: &str, mut lookup: impl FnMut(&str) -> Result<String, ContextfulError>) -> Result<String, ContextfulError> {
+    let mut out = String::new();
+    let mut rest = str;
+    while let Some(idx) = rest.find('$') {
+        let after = &rest[idx + 1..];
+        if let Some(escaped) = after.strip_prefix("${") {
+            out.push_str(&rest[..idx]);
+            out.push_str("${");
+            rest = escaped;
+        } else if let Some(reference) = after.strip_prefix('{') {
+            let Some(end) = reference.find('}') else {
+                return Err(ContextfulError::from(format!("Unterminated reference ${{{reference} : missing }}")))
+            };
+            out.push_str(&rest[..idx]);
+            out.push_str(&lookup(&reference[..end])?);
+            rest = &reference[end + 1..];
+        } else {
+            out.push_str(&rest[..=idx]);
+            rest = after;
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// What `${env:NAME}` references become
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum EnvRefs {
+    /// The value of the environment variable when goombuild runs
+    #[default]
+    Resolve,
+    /// A `$(NAME)` reference, for make to take the variable from its environment
+    Make
+}
+
+impl FromStr for EnvRefs {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "resolve" => Ok(EnvRefs::Resolve),
+            "make" => Ok(EnvRefs::Make),
+            _ => Err("environment references should be either resolve or make")
+        }
+    }
+}
+
+/// Replaces the `${env:NAME}` and `${env:NAME:-default}` references, keeping the defaults given to make
+#[derive(Default)]
+struct Env {
+    refs: EnvRefs,
+    defaults: Vec<(String, String)>
+}
+
+impl Env {
+    /// The value of a reference, None if it isn't about the environment
+    fn lookup(&mut self, reference: &str) -> Option<Result<String, ContextfulError>> {
+        let reference = reference.strip_prefix("env:")?;
+        let (name, default) = match reference.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (reference, None)
+        };
+        let valid = name.chars().next().is_some_and(|c| !c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Some(Err(ContextfulError::from(format!("{name} is not a valid environment variable name : use letters, digits and _"))))
+        }
+        Some(match self.refs {
+            //like the shell, an empty variable gets the default too
+            EnvRefs::Resolve => match (env::var(name).ok().filter(|value| !value.is_empty()), default) {
+                (Some(value), _) => Ok(value),
+                (None, Some(default)) => Ok(default.to_owned()),
+                (None, None) => Err(ContextfulError::from(format!("Environment variable {name} is not set, give it a default with ${{env:{name}:-default}}")).with_code("undefined-variable"))
+            },
+            EnvRefs::Make => {
+                let known = self.defaults.iter().find(|(known, _)| known == name);
+                match (known, default) {
+                    (Some((_, known)), Some(default)) if known != default => {
+                        return Some(Err(ContextfulError::from(format!("Environment variable {name} is given different defaults : {known} and {default}"))))
+                    },
+                    (None, Some(default)) => self.defaults.push((name.to_owned(), default.to_owned())),
+                    _ => ()
+                }
+                Ok(format!("$({name})"))
+            }
+        })
+    }
+}
+
+fn undefined(name: &str) -> ContextfulError {
+    ContextfulError::from(format!("${{{name}}} is not defined, add it to vars (or write $${{{name}}} to leave it to make)")).with_code("undefined-variable")
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Values of the `vars` table, with the variables they use replaced
+#[derive(Default)]
+struct Vars<'a> {
+    definitions: HashMap<&'a str, &'a Yaml>,
+    //None for variables that couldn't be resolved, already reported
+    resolved: HashMap<&'a str, Option<String>>,
+    resolving: Vec<&'a str>,
+    env: Env
+}
+
+impl<'a> Vars<'a> {
+    fn read(vars: &'a Hash, env: Env, diagnostics: &mut Diagnostics) -> Self {
+        let mut definitions = HashMap::new();
+        //resolved in the order of the file, so that errors are reported in that order too
+        let mut names = Vec::new();
+        for (key, value) in vars {
+            match key {
+                Yaml::String(name) if valid_name(name) => {
+                    definitions.insert(name.as_str(), value);
+                    names.push(name.as_str());
+                },
+                Yaml::String(name) => diagnostics.error(ContextfulError::from(format!("{name} is not a valid variable name : use letters, digits, _ and -")).add_context("In vars").at(key)),
+                _ => diagnostics.error(handle_wrong_type(key, "string").add_context("In vars variable name"))
+            }
+        }
+        let mut vars = Vars{definitions, env, ..Default::default()};
+        for name in names {
+            vars.resolve(name, diagnostics);
+        }
+        vars
+    }
+
+    fn resolve(&mut self, name: &'a str, diagnostics: &mut Diagnostics) -> Option<String> {
+        if let Some(value) = self.resolved.get(name) {
+            return value.clone()
+        }
+        let node = self.definitions[name];
+        if let Some(start) = self.resolving.iter().position(|resolving| *resolving == name) {
+            let mut cycle = self.resolving[start..].to_vec();
+            cycle.push(name);
+            diagnostics.error(ContextfulError::from(format!("Variable {name} is defined in terms of itself : {}", cycle.join(" -> "))).with_code("variable-cycle").at(node));
+            return None
+        }
+        let text = match node {
+            Yaml::String(str) => str.clone(),
+            Yaml::Integer(n) => n.to_string(),
+            Yaml::Real(str) => str.clone(),
+            Yaml::Boolean(b) => b.to_string(),
+            val => {
+                diagnostics.error(handle_wrong_type(val, "string").add_context(format!("In vars {name}")));
+                self.resolved.insert(name, None);
+                return None
+            }
+        };
+
+        self.resolving.push(name);
+        let mut failed = false;
+        let value = substitute(&text, |reference| {
+            if let Some(value) = self.env.lookup(reference) {
+                return value
+            }
+            let Some((&reference, _)) = self.definitions.get_key_value(reference) else {return Err(undefined(reference))};
+            //a variable that can't be resolved was already reported
+            Ok(self.resolve(reference, diagnostics).unwrap_or_else(|| {failed = true; String::new()}))
+        });
+        self.resolving.pop();
+
+        let value = match value {
+            Ok(value) if !failed => Some(value),
+            Ok(_) => None,
+            Err(err) => {
+                diagnostics.error(err.add_context(format!("In vars {name}")).at(node));
+                None
+            }
+        };
+        self.resolved.insert(name, value.clone());
+        value
+    }
+}
+
+/// Replaces the references in every string under `yaml`, `path` being where it is in the config
+fn substitute_all(yaml: &mut Yaml, path: &str, vars: &HashMap<String, Option<String>>, env: &mut Env, diagnostics: &mut Diagnostics) {
+    let in_path = |path: &str, key: &str| if path.is_empty() {key.to_owned()} else {format!("{path}.{key}")};
+    match yaml {
+        Yaml::String(str) => {
+            let value = substitute(str, |name| match (env.lookup(name), vars.get(name)) {
+                (Some(value), _) => value,
+                (None, Some(value)) => Ok(value.clone().unwrap_or_default()),
+                (None, None) => Err(undefined(name))
+            });
+            match value {
+                Ok(value) => *str = value,
+                Err(err) => diagnostics.error(err.add_context(format!("In {path}")).at(&*yaml))
+            }
+        },
+        Yaml::Array(arr) => {
+            for (i, yaml) in arr.iter_mut().enumerate() {
+                substitute_all(yaml, &in_path(path, &i.to_string()), vars, env, diagnostics);
+            }
+        },
+        Yaml::Hash(hash) => {
+            for (key, yaml) in hash.iter_mut() {
+                let key = match key {
+                    Yaml::String(str) | Yaml::Real(str) => str.clone(),
+                    Yaml::Integer(n) => n.to_string(),
+                    _ => String::from("?")
+                };
+                //the variables were resolved on their own
+                if path.is_empty() && key == "vars" {
+                    continue
+                }
+                substitute_all(yaml, &in_path(path, &key), vars, env, diagnostics);
+            }
+        },
+        _ => ()
+    }
+}
+
+/// Replaces `${name}` in every string of the config with the value of `name` in its `vars` table,
+/// and `${env:NAME}` according to `env_refs`. Returns the defaults of the environment variables left to make
+pub fn interpolate(data: &mut Yaml, env_refs: EnvRefs, diagnostics: &mut Diagnostics) -> Vec<(String, String)> {
+    let env = Env{refs: env_refs, defaults: Vec::new()};
+    let (resolved, mut env) = {
+        let vars = match data {
+            Yaml::Hash(hash) => get_data(hash, "vars"),
+            _ => None
+        };
+        let vars = match vars {
+            None | Some(Yaml::Null) => Vars{env, ..Default::default()},
+            Some(Yaml::Hash(vars)) => Vars::read(vars, env, diagnostics),
+            Some(val) => {
+                diagnostics.error(handle_wrong_type(val, "table").add_context("vars"));
+                return Vec::new()
+            }
+        };
+        //owned, as the tree is about to change
+        let resolved: HashMap<String, Option<String>> = vars.resolved.into_iter().map(|(name, value)| (name.to_owned(), value)).collect();
+        (resolved, vars.env)
+    };
+    substitute_all(data, "", &resolved, &mut env, diagnostics);
+    env.defaults
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use yaml_rust2::Yaml;
+
+    use crate::diagnostics::Diagnostics;
+    use crate::test_util::yaml;
+    use super::{interpolate, substitute, Env, EnvRefs};
+
+    fn upper(reference: &str) -> Result<String, crate::read_yaml::ContextfulError> {
+        Ok(reference.to_uppercase())
+    }
+
+    /// The config once interpolated, with the messages of its errors
+    fn interpolated(source: &str) -> (Yaml, Vec<String>) {
+        let mut doc = yaml(source);
+        let mut diagnostics = Diagnostics::new(false);
+        interpolate(&mut doc, EnvRefs::Resolve, &mut diagnostics);
+        let errors = diagnostics.iter().map(|diag| diag.error.to_string()).collect();
+        (doc, errors)
+    }
+
+    /// Name of an environment variable no other test uses
+    fn env_name(name: &str) -> String {
+        format!("GOOMBUILD_TEST_{}_{name}", process::id())
+    }
+
+    fn lookup(env: &mut Env, reference: &str) -> Option<Result<String, String>> {
+        env.lookup(reference).map(|value| value.map_err(|err| err.to_string()))
+    }
+
+    #[test]
+    fn references_are_replaced() {
+        assert_eq!(substitute("-I${inc}/x ${a}${b}", upper).ok().as_deref(), Some("-IINC/x AB"));
+        assert_eq!(substitute("no references, $(MAKE) or $x", upper).ok().as_deref(), Some("no references, $(MAKE) or $x"));
+    }
+
+    #[test]
+    fn escaped_references_are_kept() {
+        assert_eq!(substitute("$${HOME}/lib ${a}", upper).ok().as_deref(), Some("${HOME}/lib A"));
+    }
+
+    #[test]
+    fn unterminated_reference() {
+        assert!(substitute("${oops", upper).is_err());
+    }
+
+    #[test]
+    fn variables_use_each_other() {
+        let (doc, errors) = interpolated("vars:\n  root: out\n  obj: ${root}/obj\nobj_dir: ${obj}\n");
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(doc["obj_dir"].as_str(), Some("out/obj"));
+    }
+
+    #[test]
+    fn cycles_are_reported_once() {
+        let (_, errors) = interpolated("vars:\n  a: ${b}\n  b: ${a}\nobj_dir: ${a}\n");
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(errors[0].contains("a -> b -> a"), "{errors:?}");
+    }
+
+    #[test]
+    fn undefined_variable() {
+        let (_, errors) = interpolated("obj_dir: ${nope}\n");
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(errors[0].contains("${nope} is not defined"), "{errors:?}");
+    }
+
+    #[test]
+    fn environment_variables_are_resolved() {
+        let (set, empty, unset) = (env_name("SET"), env_name("EMPTY"), env_name("UNSET"));
+        env::set_var(&set, "value");
+        env::set_var(&empty, "");
+        let mut env = Env::default();
+        assert_eq!(lookup(&mut env, &format!("env:{set}")), Some(Ok("value".to_owned())));
+        assert_eq!(lookup(&mut env, &format!("env:{set}:-default")), Some(Ok("value".to_owned())));
+        assert_eq!(lookup(&mut env, &format!("env:{empty}:-default")), Some(Ok("default".to_owned())));
+        assert_eq!(lookup(&mut env, &format!("env:{unset}:-")), Some(Ok(String::new())));
+        assert!(lookup(&mut env, &format!("env:{unset}")).is_some_and(|value| value.is_err_and(|err| err.contains("is not set"))));
+        assert!(lookup(&mut env, "env:1ST").is_some_and(|value| value.is_err()));
+        assert_eq!(lookup(&mut env, "name"), None);
+    }
+
+    #[test]
+    fn environment_variables_are_left_to_make() {
+        let mut doc = yaml("obj_dir: ${env:OUT:-out}/obj\nbin_dir: ${env:OUT:-out}\ninclude: ${env:SDK}/include\n");
+        let mut diagnostics = Diagnostics::new(false);
+        let defaults = interpolate(&mut doc, EnvRefs::Make, &mut diagnostics);
+        assert_eq!(diagnostics.iter().count(), 0);
+        assert_eq!(doc, yaml("obj_dir: $(OUT)/obj\nbin_dir: $(OUT)\ninclude: $(SDK)/include\n"));
+        assert_eq!(defaults, vec![("OUT".to_owned(), "out".to_owned())]);
+    }
+
+    #[test]
+    fn environment_variables_have_one_default() {
+        let mut env = Env{refs: EnvRefs::Make, defaults: Vec::new()};
+        assert_eq!(lookup(&mut env, "env:OUT:-out"), Some(Ok("$(OUT)".to_owned())));
+        assert_eq!(lookup(&mut env, "env:OUT"), Some(Ok("$(OUT)".to_owned())));
+        assert!(lookup(&mut env, "env:OUT:-build").is_some_and(|value| value.is_err_and(|err| err.contains("different defaults : out and build"))));
+        assert_eq!(env.defaults, vec![("OUT".to_owned(), "out".to_owned())]);
+    }
+}